@@ -0,0 +1,68 @@
+use app_lib::decoder::{Checksum, Protocol};
+use app_lib::stats::StreamStats;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+// A 16-channel Mega board: sync, counter, 16 samples, end byte.
+const CHANNELS: usize = 16;
+const PACKETS: usize = 10_000;
+// Typical size of a serial read.
+const READ_LEN: usize = 1024;
+
+fn packet_size(width: usize) -> usize {
+    3 + width * CHANNELS + 1
+}
+
+/// `PACKETS` packets of `width`-byte samples, with `noise` junk bytes before
+/// each one.
+fn stream(noise: usize, width: usize) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(PACKETS * (packet_size(width) + noise));
+    for n in 0..PACKETS {
+        bytes.extend((0..noise).map(|i| (i * 31 + n) as u8 & 0x7F));
+        bytes.extend_from_slice(&[0xC7, 0x7C, n as u8]);
+        for channel in 0..CHANNELS {
+            let value = ((n + channel) as i32).to_be_bytes();
+            bytes.extend_from_slice(&value[4 - width..]);
+        }
+        bytes.push(0x01);
+    }
+    bytes
+}
+
+fn serial(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serial");
+
+    for (name, noise, width) in [("clean", 0, 2), ("noisy", 8, 2), ("24-bit", 0, 3)] {
+        let protocol = Protocol::Serial {
+            packet_size: packet_size(width),
+            channels: CHANNELS,
+            sample_width: width,
+            checksum: Checksum::None,
+            unsigned: false,
+        };
+        let bytes = stream(noise, width);
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || protocol.decoder(),
+                |mut decoder| {
+                    let stats = StreamStats::default();
+                    let mut count = 0;
+                    let mut sum = 0i64;
+                    for read in bytes.chunks(READ_LEN) {
+                        decoder.feed(read, &stats, |sample| {
+                            count += 1;
+                            sum += sample[0] as i64;
+                        });
+                    }
+                    assert_eq!(count, PACKETS);
+                    sum
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, serial);
+criterion_main!(benches);
@@ -0,0 +1,262 @@
+use crate::decoder::{Checksum, Protocol};
+use crate::scaling::Scaling;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+/// Version of the `INFO` descriptor this connector understands.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// What the connector needs to know about a board running Chords firmware.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BoardProfile {
+    /// Name the firmware answers `WHORU` with.
+    pub name: String,
+    pub channels: usize,
+    /// Only known from an `INFO` descriptor; the legacy table leaves it to
+    /// the USB bridge the board was found behind.
+    pub sample_rate: Option<f64>,
+    /// ADC resolution in bits.
+    pub resolution: u32,
+    /// ADC reference voltage in volts.
+    pub vref: f64,
+    /// Bytes per sample on the wire: 2, or 3 for 24-bit frontends.
+    pub sample_width: usize,
+    /// Samples are two's complement around zero volts, as delta-sigma
+    /// frontends deliver them, rather than offset from mid-scale.
+    pub bipolar: bool,
+    #[serde(default)]
+    pub checksum: Checksum,
+}
+
+impl BoardProfile {
+    fn new(name: &str, channels: usize, resolution: u32, vref: f64) -> Self {
+        BoardProfile {
+            name: name.to_string(),
+            channels,
+            sample_rate: None,
+            resolution,
+            vref,
+            sample_width: 2,
+            bipolar: false,
+            checksum: Checksum::None,
+        }
+    }
+
+    fn wide(self) -> Self {
+        BoardProfile {
+            sample_width: 3,
+            bipolar: true,
+            ..self
+        }
+    }
+
+    /// Sync bytes, counter, samples, checksum and end byte.
+    pub fn packet_size(&self) -> usize {
+        3 + self.channels * self.sample_width + self.checksum.trailer_len() + 1
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        Protocol::Serial {
+            packet_size: self.packet_size(),
+            channels: self.channels,
+            sample_width: self.sample_width,
+            checksum: self.checksum,
+            unsigned: !self.bipolar,
+        }
+    }
+
+    pub fn scaling(&self) -> Scaling {
+        Scaling {
+            bipolar: self.bipolar,
+            ..Scaling::new(self.resolution, self.vref)
+        }
+    }
+}
+
+impl Default for BoardProfile {
+    /// Six 10-bit channels, the layout of the classic Arduino boards.
+    fn default() -> Self {
+        BoardProfile::new("UNKNOWN", 6, 10, 5.0)
+    }
+}
+
+lazy_static! {
+    static ref BOARDS: Vec<BoardProfile> = vec![
+        BoardProfile::new("UNO-R3", 6, 10, 5.0),
+        BoardProfile::new("UNO-CLONE", 6, 10, 5.0),
+        BoardProfile::new("GENUINO-UNO", 6, 10, 5.0),
+        BoardProfile::new("NANO-CLONE", 8, 10, 5.0),
+        BoardProfile::new("NANO-CLASSIC", 8, 10, 5.0),
+        BoardProfile::new("MEGA-2560-R3", 16, 10, 5.0),
+        BoardProfile::new("MEGA-2560-CLONE", 16, 10, 5.0),
+        BoardProfile::new("UNO-R4", 6, 14, 5.0),
+        BoardProfile::new("GIGA-R1", 6, 16, 3.3),
+        BoardProfile::new("RPI-PICO-RP2040", 3, 12, 3.3),
+        BoardProfile::new("NPG-LITE", 3, 12, 3.3),
+        BoardProfile::new("STM32G4-CORE-BOARD", 16, 12, 3.3),
+        BoardProfile::new("STM32F4-BLACK-PILL", 8, 12, 3.3),
+        BoardProfile::new("ADS1299", 8, 24, 4.5).wide(),
+        BoardProfile::new("ADS1298", 8, 24, 2.4).wide(),
+    ];
+}
+
+/// The profile of the board whose name appears in a `WHORU` response.
+pub fn identify(response: &str) -> Option<BoardProfile> {
+    BOARDS
+        .iter()
+        .find(|board| response.contains(&board.name))
+        .cloned()
+}
+
+/// The profile named `name` in the board table.
+pub fn named(name: &str) -> Option<BoardProfile> {
+    BOARDS.iter().find(|board| board.name == name).cloned()
+}
+
+/// Every board in the table, for choosing one by hand.
+pub fn profiles() -> Vec<BoardProfile> {
+    BOARDS.clone()
+}
+
+/// Reply of newer firmware to `INFO`: one line of JSON such as
+/// `{"version":1,"board":"NANO-CLONE","channels":8,"sampleRate":500,
+/// "resolution":10,"vref":5.0,"frame":{"sampleWidth":2,"packetSize":20},
+/// "checksum":"none"}`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    version: u32,
+    board: String,
+    channels: usize,
+    sample_rate: f64,
+    resolution: u32,
+    vref: f64,
+    #[serde(default)]
+    bipolar: bool,
+    frame: FrameLayout,
+    #[serde(default)]
+    checksum: Checksum,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FrameLayout {
+    sample_width: usize,
+    /// Checked against the size the other fields add up to.
+    packet_size: Option<usize>,
+}
+
+/// Reads the descriptor out of an `INFO` response. `None` while no complete
+/// JSON line has arrived, an error if the descriptor can't be used.
+pub fn parse_info(response: &str) -> Option<Result<BoardProfile, String>> {
+    let start = response.find('{')?;
+    let end = start + response[start..].find('\n')?;
+    Some(profile_from(response[start..end].trim_end()))
+}
+
+fn profile_from(line: &str) -> Result<BoardProfile, String> {
+    let descriptor: Descriptor =
+        serde_json::from_str(line).map_err(|e| format!("Invalid INFO descriptor: {}", e))?;
+    if descriptor.version != PROTOCOL_VERSION {
+        return Err(format!(
+            "Unsupported INFO protocol version {}",
+            descriptor.version
+        ));
+    }
+    if !(1..=4).contains(&descriptor.frame.sample_width)
+        || !(1..=32).contains(&descriptor.resolution)
+        || descriptor.channels == 0
+    {
+        return Err("INFO descriptor is out of range".to_string());
+    }
+
+    let profile = BoardProfile {
+        name: descriptor.board,
+        channels: descriptor.channels,
+        sample_rate: Some(descriptor.sample_rate).filter(|rate| *rate > 0.0),
+        resolution: descriptor.resolution,
+        vref: descriptor.vref,
+        sample_width: descriptor.frame.sample_width,
+        bipolar: descriptor.bipolar,
+        checksum: descriptor.checksum,
+    };
+    match descriptor.frame.packet_size {
+        Some(size) if size != profile.packet_size() => Err(format!(
+            "INFO packet size {} doesn't match {} channels of {} bytes with {:?} checksum",
+            size, profile.channels, profile.sample_width, profile.checksum
+        )),
+        _ => Ok(profile),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: &str = r#"{"version":1,"board":"NANO-CLONE","channels":8,"sampleRate":500,"resolution":10,"vref":5.0,"frame":{"sampleWidth":2,"packetSize":20},"checksum":"none"}"#;
+
+    #[test]
+    fn parses_a_descriptor_after_other_output() {
+        let profile = parse_info(&format!("booting\r\n{}\r\n", INFO))
+            .unwrap()
+            .unwrap();
+        assert_eq!(profile.name, "NANO-CLONE");
+        assert_eq!(profile.channels, 8);
+        assert_eq!(profile.sample_rate, Some(500.0));
+        assert_eq!(profile.packet_size(), 20);
+    }
+
+    #[test]
+    fn waits_for_the_end_of_the_line() {
+        assert!(parse_info("").is_none());
+        assert!(parse_info("NANO-CLONE\n").is_none());
+        assert!(parse_info(&INFO[..40]).is_none());
+        assert!(parse_info(INFO).is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_descriptors() {
+        let broken = [
+            // Cut short, not JSON at all, and a field of the wrong type.
+            format!("{}\n", &INFO[..40]),
+            "{ok}\n".to_string(),
+            format!("{}\n", INFO.replace(r#""channels":8"#, r#""channels":"8""#)),
+            // A missing field.
+            format!("{}\n", INFO.replace(r#""vref":5.0,"#, "")),
+            // Unknown protocol version.
+            format!("{}\n", INFO.replace(r#""version":1"#, r#""version":2"#)),
+            // Out of range.
+            format!("{}\n", INFO.replace(r#""channels":8"#, r#""channels":0"#)),
+            format!(
+                "{}\n",
+                INFO.replace(r#""sampleWidth":2"#, r#""sampleWidth":5"#)
+            ),
+            format!(
+                "{}\n",
+                INFO.replace(r#""resolution":10"#, r#""resolution":33"#)
+            ),
+            // A packet size the other fields don't add up to.
+            format!(
+                "{}\n",
+                INFO.replace(r#""packetSize":20"#, r#""packetSize":21"#)
+            ),
+        ];
+        for response in &broken {
+            assert!(
+                matches!(parse_info(response), Some(Err(_))),
+                "accepted {}",
+                response
+            );
+        }
+    }
+
+    #[test]
+    fn identifies_boards_by_name() {
+        assert_eq!(
+            identify("UNO-R4\r\n").map(|board| board.name),
+            Some("UNO-R4".to_string())
+        );
+        assert!(identify("UNKNOWN\r\n").is_none());
+    }
+}
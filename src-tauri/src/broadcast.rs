@@ -0,0 +1,444 @@
+use crate::session::select_channels;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tungstenite::handshake::server::{ErrorResponse, Request as HandshakeRequest};
+use tungstenite::http::StatusCode;
+use tungstenite::{Error, Message, WebSocket};
+
+pub const DEFAULT_PORT: u16 = 8765;
+// Batches per second sent to each client.
+const BATCH_RATE: f64 = 25.0;
+// Batches a slow client may fall behind before new ones are dropped.
+const CLIENT_QUEUE: usize = 16;
+
+/// A run of consecutive samples of one session.
+pub struct Batch {
+    session: String,
+    sample_rate: f64,
+    channels: usize,
+    /// LSL timestamp of the last sample.
+    timestamp: f64,
+    /// Counts don't fit in 16 bits.
+    wide: bool,
+    counts: Vec<i32>,
+    microvolts: Vec<f32>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    #[default]
+    Json,
+    Binary,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Unit {
+    #[default]
+    Counts,
+    Microvolts,
+}
+
+/// Messages clients send to the server.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Request {
+    /// Lists the sessions currently streaming.
+    List,
+    Subscribe {
+        session: String,
+        /// 1-based channels; empty means all of them.
+        #[serde(default)]
+        channels: Vec<usize>,
+        #[serde(default)]
+        format: Format,
+        #[serde(default)]
+        unit: Unit,
+    },
+    Unsubscribe,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionInfo {
+    session: String,
+    channels: usize,
+    sample_rate: f64,
+}
+
+struct Subscription {
+    session: String,
+    channels: Vec<usize>,
+    format: Format,
+    unit: Unit,
+}
+
+lazy_static! {
+    static ref CLIENTS: Mutex<Vec<Sender<Arc<Batch>>>> = Mutex::new(Vec::new());
+    // Sessions with a publisher, keyed by id, along with that publisher's number.
+    static ref SESSIONS: Mutex<HashMap<String, (u64, SessionInfo)>> = Mutex::new(HashMap::new());
+    // The thread accepting connections, which owns the listening socket.
+    static ref ACCEPTOR: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+}
+static PUBLISHERS: AtomicU64 = AtomicU64::new(0);
+// Set while at least one client is connected, so pipelines skip batching otherwise.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+// Bumped by every start and stop; a server runs while it holds the latest value.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Starts the server on localhost, replacing a running one. Browsers may only
+/// connect from a local page or one of `origins`; tools that send no
+/// `Origin` header are always let in.
+pub fn start(port: u16, origins: Vec<String>) -> Result<(), String> {
+    let mut acceptor = ACCEPTOR.lock().unwrap();
+    // The old server has to let go of its port before a restart on the same
+    // one can bind it.
+    let generation = shut_down(&mut acceptor);
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .map_err(|e| format!("Failed to listen on port {}: {}", port, e))?;
+    listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+    println!("Broadcast server listening on ws://127.0.0.1:{}", port);
+    let origins = Arc::new(origins);
+
+    *acceptor = Some(thread::spawn(move || {
+        while GENERATION.load(Ordering::SeqCst) == generation {
+            match listener.accept() {
+                Ok((stream, address)) => {
+                    println!("Broadcast client connected: {}", address);
+                    let origins = origins.clone();
+                    thread::spawn(move || serve(stream, &origins, generation));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(50));
+                }
+                Err(e) => println!("Broadcast accept failed: {}", e),
+            }
+        }
+        println!("Broadcast server on port {} stopped", port);
+    }));
+    Ok(())
+}
+
+pub fn stop() {
+    shut_down(&mut ACCEPTOR.lock().unwrap());
+}
+
+// Stops the running server and waits for its listener to close. Returns the
+// new generation.
+fn shut_down(acceptor: &mut Option<JoinHandle<()>>) -> u64 {
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    if let Some(thread) = acceptor.take() {
+        let _ = thread.join();
+    }
+    generation
+}
+
+// Any web page the user has open could otherwise read their signals.
+fn check_origin(request: &HandshakeRequest, origins: &[String]) -> Result<(), String> {
+    let origin = match request.headers().get("Origin") {
+        Some(origin) => origin.to_str().unwrap_or(""),
+        None => return Ok(()),
+    };
+    let host = origin
+        .split("://")
+        .nth(1)
+        .map(|rest| rest.rsplit_once(':').map_or(rest, |(host, _)| host))
+        .unwrap_or("");
+    let local = matches!(
+        host,
+        "localhost" | "127.0.0.1" | "[::1]" | "tauri.localhost"
+    );
+    if local || origins.iter().any(|allowed| allowed == origin) {
+        Ok(())
+    } else {
+        Err(format!("Origin {} is not allowed", origin))
+    }
+}
+
+fn serve(stream: TcpStream, origins: &[String], generation: u64) {
+    let _ = stream.set_nonblocking(false);
+    // The error type is tungstenite's.
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &HandshakeRequest, response| match check_origin(request, origins) {
+        Ok(()) => Ok(response),
+        Err(message) => {
+            println!("Broadcast client rejected: {}", message);
+            let mut rejection = ErrorResponse::new(Some(message));
+            *rejection.status_mut() = StatusCode::FORBIDDEN;
+            Err(rejection)
+        }
+    };
+    let mut socket = match tungstenite::accept_hdr(stream, callback) {
+        Ok(socket) => socket,
+        Err(e) => {
+            println!("Broadcast handshake failed: {}", e);
+            return;
+        }
+    };
+    // Short reads so the same thread can also forward batches.
+    let _ = socket
+        .get_ref()
+        .set_read_timeout(Some(Duration::from_millis(10)));
+
+    let (tx, rx) = bounded(CLIENT_QUEUE);
+    {
+        let mut clients = CLIENTS.lock().unwrap();
+        clients.push(tx.clone());
+        ACTIVE.store(true, Ordering::Relaxed);
+    }
+
+    let result = run_client(&mut socket, &rx, generation);
+    if let Err(e) = result {
+        println!("Broadcast client disconnected: {}", e);
+    }
+    let _ = socket.close(None);
+
+    let mut clients = CLIENTS.lock().unwrap();
+    clients.retain(|client| !client.same_channel(&tx));
+    ACTIVE.store(!clients.is_empty(), Ordering::Relaxed);
+}
+
+fn run_client(
+    socket: &mut WebSocket<TcpStream>,
+    batches: &Receiver<Arc<Batch>>,
+    generation: u64,
+) -> Result<(), String> {
+    let mut subscription: Option<Subscription> = None;
+
+    while GENERATION.load(Ordering::SeqCst) == generation {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                let reply = match serde_json::from_str::<Request>(&text) {
+                    Ok(Request::List) => json!({ "type": "sessions", "sessions": sessions() }),
+                    Ok(Request::Subscribe {
+                        session,
+                        channels,
+                        format,
+                        unit,
+                    }) => {
+                        // Sessions that aren't streaming yet are checked once
+                        // their samples arrive.
+                        let known = SESSIONS
+                            .lock()
+                            .unwrap()
+                            .get(&session)
+                            .map(|(_, info)| info.channels);
+                        match known.map(|count| select_channels(&channels, count)) {
+                            Some(Err(e)) => json!({ "type": "error", "message": e }),
+                            _ => {
+                                let reply = json!({
+                                    "type": "subscribed",
+                                    "session": session,
+                                    "channels": channels,
+                                });
+                                subscription = Some(Subscription {
+                                    session,
+                                    channels,
+                                    format,
+                                    unit,
+                                });
+                                reply
+                            }
+                        }
+                    }
+                    Ok(Request::Unsubscribe) => {
+                        subscription = None;
+                        json!({ "type": "unsubscribed" })
+                    }
+                    Err(e) => json!({ "type": "error", "message": e.to_string() }),
+                };
+                socket
+                    .send(Message::Text(reply.to_string()))
+                    .map_err(|e| e.to_string())?;
+            }
+            Ok(Message::Close(_)) => return Ok(()),
+            Ok(_) => {}
+            // Nothing to read within the timeout.
+            Err(Error::Io(e)) if is_timeout(&e) => {}
+            Err(e) => return Err(e.to_string()),
+        }
+
+        while let Ok(batch) = batches.try_recv() {
+            let frame = match &subscription {
+                Some(subscription) if subscription.session == batch.session => {
+                    encode(&batch, subscription)
+                }
+                _ => continue,
+            };
+            // A session that came back with fewer channels ends the
+            // subscription.
+            let frame = frame.unwrap_or_else(|e| {
+                subscription = None;
+                Message::Text(json!({ "type": "error", "message": e }).to_string())
+            });
+            socket.write(frame).map_err(|e| e.to_string())?;
+        }
+        socket.flush().map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+fn sessions() -> Vec<SessionInfo> {
+    SESSIONS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|(_, info)| info.clone())
+        .collect()
+}
+
+/// JSON frames look like `{"session", "timestamp", "sampleRate", "channels",
+/// "samples": [[ch1, ch2, ...], ...]}`. Binary frames are little-endian: u8
+/// type (0 counts as i16, 1 microvolts as f32, 2 counts of ADCs wider than 16
+/// bits as i32), u16 channel count, u32 sample count, f64 timestamp of the last
+/// sample, then the interleaved samples. Fails if the subscription names a
+/// channel the batch doesn't have.
+fn encode(batch: &Batch, subscription: &Subscription) -> Result<Message, String> {
+    let channels = select_channels(&subscription.channels, batch.channels)?;
+    let len = batch.counts.len() / batch.channels.max(1);
+    let value = |i: usize, c: usize| -> f64 {
+        let at = i * batch.channels + c - 1;
+        match subscription.unit {
+            Unit::Counts => batch.counts[at] as f64,
+            Unit::Microvolts => batch.microvolts[at] as f64,
+        }
+    };
+
+    match subscription.format {
+        Format::Json => {
+            let samples: Vec<Vec<f64>> = (0..len)
+                .map(|i| channels.iter().map(|&c| value(i, c)).collect())
+                .collect();
+            let frame = json!({
+                "session": batch.session,
+                "timestamp": batch.timestamp,
+                "sampleRate": batch.sample_rate,
+                "channels": channels,
+                "samples": samples,
+            });
+            Ok(Message::Text(frame.to_string()))
+        }
+        Format::Binary => {
+            let mut frame = Vec::with_capacity(15 + len * channels.len() * 4);
+            frame.push(match subscription.unit {
+                Unit::Counts if batch.wide => 2,
+                unit => unit as u8,
+            });
+            frame.extend_from_slice(&(channels.len() as u16).to_le_bytes());
+            frame.extend_from_slice(&(len as u32).to_le_bytes());
+            frame.extend_from_slice(&batch.timestamp.to_le_bytes());
+            for i in 0..len {
+                for &c in &channels {
+                    match subscription.unit {
+                        Unit::Counts if batch.wide => {
+                            frame.extend_from_slice(&(value(i, c) as i32).to_le_bytes())
+                        }
+                        Unit::Counts => {
+                            frame.extend_from_slice(&(value(i, c) as i16).to_le_bytes())
+                        }
+                        Unit::Microvolts => {
+                            frame.extend_from_slice(&(value(i, c) as f32).to_le_bytes())
+                        }
+                    }
+                }
+            }
+            Ok(Message::Binary(frame))
+        }
+    }
+}
+
+/// Collects a session's samples in the pipeline and hands them to the
+/// connected clients in batches. Does nothing while no client is connected.
+pub struct Publisher {
+    id: u64,
+    session: String,
+    sample_rate: f64,
+    channels: usize,
+    wide: bool,
+    per_batch: usize,
+    counts: Vec<i32>,
+    microvolts: Vec<f32>,
+}
+
+impl Publisher {
+    pub fn new(session: &str, channels: usize, sample_rate: f64, wide: bool) -> Self {
+        let id = PUBLISHERS.fetch_add(1, Ordering::Relaxed);
+        let info = SessionInfo {
+            session: session.to_string(),
+            channels,
+            sample_rate,
+        };
+        SESSIONS
+            .lock()
+            .unwrap()
+            .insert(session.to_string(), (id, info));
+        Publisher {
+            id,
+            session: session.to_string(),
+            sample_rate,
+            channels,
+            wide,
+            per_batch: ((sample_rate / BATCH_RATE) as usize).max(1),
+            counts: Vec::new(),
+            microvolts: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, counts: &[i32], microvolts: &[f32]) {
+        if !ACTIVE.load(Ordering::Relaxed) {
+            self.counts.clear();
+            self.microvolts.clear();
+            return;
+        }
+
+        self.counts.extend_from_slice(counts);
+        self.microvolts.extend_from_slice(microvolts);
+        if self.counts.len() < self.per_batch * self.channels {
+            return;
+        }
+
+        let capacity = self.per_batch * self.channels;
+        let batch = Arc::new(Batch {
+            session: self.session.clone(),
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            timestamp: lsl::local_clock(),
+            wide: self.wide,
+            counts: std::mem::replace(&mut self.counts, Vec::with_capacity(capacity)),
+            microvolts: std::mem::replace(&mut self.microvolts, Vec::with_capacity(capacity)),
+        });
+        for client in CLIENTS.lock().unwrap().iter() {
+            let _ = client.try_send(batch.clone());
+        }
+    }
+}
+
+impl Drop for Publisher {
+    fn drop(&mut self) {
+        // A newer publisher of the same session may have taken over already.
+        let mut sessions = SESSIONS.lock().unwrap();
+        if sessions
+            .get(&self.session)
+            .is_some_and(|(id, _)| *id == self.id)
+        {
+            sessions.remove(&self.session);
+        }
+    }
+}
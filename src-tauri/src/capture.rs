@@ -0,0 +1,306 @@
+use crate::decoder::Protocol;
+use crate::outlet::StreamSpec;
+use crate::pipeline::Pipeline;
+use crate::scaling::Scaling;
+use crate::session::Session;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::sync::Arc;
+use std::time::Instant;
+
+// File layout: MAGIC, u32 LE header length, JSON header, then records of
+// u64 LE microseconds since the capture started, u32 LE length and the bytes
+// exactly as the transport delivered them.
+const MAGIC: &[u8; 8] = b"CHORDCAP";
+const VERSION: u32 = 1;
+// Far above anything a writer produces; longer lengths mean a damaged file,
+// and are refused rather than allocated.
+const MAX_HEADER_LEN: usize = 64 * 1024;
+const MAX_RECORD_LEN: usize = 1024 * 1024;
+
+/// Everything needed to decode a capture and recreate its outlets.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureHeader {
+    pub version: u32,
+    pub session: String,
+    pub protocol: Protocol,
+    pub spec: StreamSpec,
+    pub scaling: Scaling,
+    /// Wall-clock start of the capture, RFC 3339.
+    pub started: String,
+}
+
+struct CaptureWriter {
+    file: BufWriter<File>,
+    start: Instant,
+}
+
+impl CaptureWriter {
+    fn create(path: &str, header: &CaptureHeader) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let json = serde_json::to_vec(header)?;
+        file.write_all(MAGIC)?;
+        file.write_all(&(json.len() as u32).to_le_bytes())?;
+        file.write_all(&json)?;
+        Ok(CaptureWriter {
+            file,
+            start: Instant::now(),
+        })
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let micros = self.start.elapsed().as_micros() as u64;
+        self.file.write_all(&micros.to_le_bytes())?;
+        self.file.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        self.file.write_all(bytes)
+    }
+}
+
+/// Records a transport's raw bytes whenever the session's `capture` setting
+/// names a file. Lives on the transport's reader thread.
+pub struct Tap {
+    session: Arc<Session>,
+    revision: u64,
+    header: CaptureHeader,
+    path: Option<String>,
+    writer: Option<CaptureWriter>,
+}
+
+impl Tap {
+    pub fn new(pipeline: &Pipeline, protocol: Protocol) -> Self {
+        Self::with_header(
+            pipeline.session(),
+            CaptureHeader {
+                version: VERSION,
+                session: pipeline.session().id.clone(),
+                protocol,
+                spec: pipeline.spec().clone(),
+                scaling: pipeline.scaling(),
+                started: String::new(),
+            },
+        )
+    }
+
+    /// A tap for `session` without a pipeline behind it.
+    pub fn with_header(session: Arc<Session>, header: CaptureHeader) -> Self {
+        Tap {
+            session,
+            revision: 0,
+            header,
+            path: None,
+            writer: None,
+        }
+    }
+
+    pub fn record(&mut self, bytes: &[u8]) {
+        let revision = self.session.revision();
+        if revision != self.revision {
+            self.revision = revision;
+            let path = self.session.settings().capture;
+            if path != self.path {
+                self.open(path);
+            }
+        }
+
+        if let Some(writer) = &mut self.writer {
+            if let Err(e) = writer.write(bytes) {
+                println!("Capture write failed, stopping capture: {}", e);
+                self.writer = None;
+            }
+        }
+    }
+
+    fn open(&mut self, path: Option<String>) {
+        // Dropping the old writer flushes it.
+        self.writer = None;
+        if let Some(path) = &path {
+            self.header.started = chrono::Utc::now().to_rfc3339();
+            match CaptureWriter::create(path, &self.header) {
+                Ok(writer) => {
+                    println!("Capturing raw {} data to {}", self.header.session, path);
+                    self.writer = Some(writer);
+                }
+                Err(e) => println!("Failed to create capture file {}: {}", path, e),
+            }
+        }
+        self.path = path;
+    }
+}
+
+/// Reads a capture back record by record.
+pub struct CaptureReader {
+    file: BufReader<File>,
+    pub header: CaptureHeader,
+}
+
+impl CaptureReader {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let mut file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+
+        let mut magic = [0; 8];
+        file.read_exact(&mut magic)
+            .map_err(|_| "Not a capture file".to_string())?;
+        if &magic != MAGIC {
+            return Err("Not a capture file".to_string());
+        }
+        let mut len = [0; 4];
+        file.read_exact(&mut len).map_err(|e| e.to_string())?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_HEADER_LEN {
+            return Err(format!("Capture header of {} bytes is too long", len));
+        }
+        let mut json = vec![0; len];
+        file.read_exact(&mut json).map_err(|e| e.to_string())?;
+        let header: CaptureHeader = serde_json::from_slice(&json).map_err(|e| e.to_string())?;
+        if header.version > VERSION {
+            return Err(format!("Unsupported capture version {}", header.version));
+        }
+
+        Ok(CaptureReader { file, header })
+    }
+
+    /// Reads the next record into `bytes` and returns its timestamp in
+    /// microseconds since the start, or `None` at the end of the file. A record
+    /// cut short by a crash while capturing also ends it.
+    pub fn next_record(&mut self, bytes: &mut Vec<u8>) -> Result<Option<u64>, String> {
+        let mut prefix = [0; 12];
+        if self.file.read_exact(&mut prefix).is_err() {
+            return Ok(None);
+        }
+        let micros = u64::from_le_bytes(prefix[..8].try_into().unwrap());
+        let len = u32::from_le_bytes(prefix[8..].try_into().unwrap()) as usize;
+        if len > MAX_RECORD_LEN {
+            return Err(format!("Capture record of {} bytes is too long", len));
+        }
+        bytes.resize(len, 0);
+        if self.file.read_exact(bytes).is_err() {
+            return Ok(None);
+        }
+        Ok(Some(micros))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Checksum;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // A path in the temp directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            TempFile(std::env::temp_dir().join(format!(
+                "chords-{}-{}.cap",
+                name,
+                std::process::id()
+            )))
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn header() -> CaptureHeader {
+        CaptureHeader {
+            version: VERSION,
+            session: "serial".to_string(),
+            protocol: Protocol::Serial {
+                packet_size: 16,
+                channels: 6,
+                sample_width: 2,
+                checksum: Checksum::Crc8,
+                unsigned: true,
+            },
+            spec: StreamSpec {
+                name: "UDL".to_string(),
+                stream_type: "EXG".to_string(),
+                channels: 6,
+                sample_rate: 500.0,
+                source_id: "serial".to_string(),
+            },
+            scaling: Scaling::new(10, 5.0),
+            started: "2024-01-01T00:00:00+00:00".to_string(),
+        }
+    }
+
+    // The file header followed by `records` as raw `(micros, length, bytes)`.
+    fn write_raw(file: &TempFile, records: &[(u64, u32, &[u8])]) {
+        let json = serde_json::to_vec(&header()).unwrap();
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&json);
+        for (micros, len, record) in records {
+            bytes.extend_from_slice(&micros.to_le_bytes());
+            bytes.extend_from_slice(&len.to_le_bytes());
+            bytes.extend_from_slice(record);
+        }
+        fs::write(&file.0, bytes).unwrap();
+    }
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let file = TempFile::new("round-trip");
+        let mut writer = CaptureWriter::create(file.path(), &header()).unwrap();
+        writer.write(&[0xC7, 0x7C, 1]).unwrap();
+        writer.write(&[]).unwrap();
+        writer.write(&[2; 300]).unwrap();
+        drop(writer);
+
+        let mut reader = CaptureReader::open(file.path()).unwrap();
+        assert_eq!(reader.header.protocol, header().protocol);
+        assert_eq!(reader.header.spec.name, "UDL");
+        let mut bytes = Vec::new();
+        let mut last = 0;
+        for expected in [&[0xC7, 0x7C, 1][..], &[], &[2; 300]] {
+            let micros = reader.next_record(&mut bytes).unwrap().unwrap();
+            assert!(micros >= last);
+            last = micros;
+            assert_eq!(bytes, expected);
+        }
+        assert_eq!(reader.next_record(&mut bytes), Ok(None));
+    }
+
+    #[test]
+    fn a_truncated_record_ends_the_capture() {
+        let file = TempFile::new("truncated");
+        write_raw(&file, &[(5, 3, &[1, 2, 3]), (9, 4, &[1, 2])]);
+        let mut reader = CaptureReader::open(file.path()).unwrap();
+        let mut bytes = Vec::new();
+        assert_eq!(reader.next_record(&mut bytes), Ok(Some(5)));
+        assert_eq!(bytes, [1, 2, 3]);
+        assert_eq!(reader.next_record(&mut bytes), Ok(None));
+    }
+
+    #[test]
+    fn rejects_an_oversize_record() {
+        let file = TempFile::new("oversize-record");
+        write_raw(&file, &[(0, u32::MAX, &[])]);
+        let mut reader = CaptureReader::open(file.path()).unwrap();
+        assert!(reader.next_record(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let file = TempFile::new("not-a-capture");
+        fs::write(&file.0, b"XDF:not a capture").unwrap();
+        assert!(CaptureReader::open(file.path()).is_err());
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&file.0, bytes).unwrap();
+        let error = CaptureReader::open(file.path()).err().unwrap();
+        assert!(error.contains("too long"));
+    }
+}
@@ -0,0 +1,549 @@
+use crate::stats::{Sequence, SequenceTracker, StreamStats};
+use serde::{Deserialize, Serialize};
+
+const START_BYTE_1: u8 = 0xC7;
+const START_BYTE_2: u8 = 0x7C;
+const END_BYTE: u8 = 0x01;
+
+// NPG-Lite WiFi frames: counter + 3 big-endian channels, padded to 13 bytes.
+const WIFI_BLOCK_LEN: usize = 13;
+const WIFI_CHANNELS: usize = 3;
+
+// NPG-Lite BLE notifications: counter + 3 big-endian channels, one or ten per packet.
+const BLE_BLOCK_COUNT: usize = 10;
+const BLE_CHANNELS: usize = 3;
+
+// Smallest serial ring; larger packets get a ring of at least two of them.
+const MIN_RING_LEN: usize = 4096;
+
+/// Optional integrity check between a serial packet's samples and its end
+/// byte, computed over the counter and the samples and sent big-endian.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Checksum {
+    #[default]
+    None,
+    /// CRC-8/SMBUS: polynomial 0x07, initial value 0.
+    Crc8,
+    /// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF.
+    Crc16,
+}
+
+impl Checksum {
+    /// Bytes the trailer adds to a packet.
+    pub fn trailer_len(self) -> usize {
+        match self {
+            Checksum::None => 0,
+            Checksum::Crc8 => 1,
+            Checksum::Crc16 => 2,
+        }
+    }
+
+    pub fn compute(self, bytes: &[u8]) -> u16 {
+        match self {
+            Checksum::None => 0,
+            Checksum::Crc8 => bytes.iter().fold(0u8, |crc, &b| {
+                (0..8).fold(crc ^ b, |crc, _| {
+                    if crc & 0x80 != 0 {
+                        (crc << 1) ^ 0x07
+                    } else {
+                        crc << 1
+                    }
+                })
+            }) as u16,
+            Checksum::Crc16 => bytes.iter().fold(0xFFFFu16, |crc, &b| {
+                (0..8).fold(crc ^ ((b as u16) << 8), |crc, _| {
+                    if crc & 0x8000 != 0 {
+                        (crc << 1) ^ 0x1021
+                    } else {
+                        crc << 1
+                    }
+                })
+            }),
+        }
+    }
+
+    /// Whether `bytes` ends in a valid trailer for what comes before it.
+    fn verify(self, bytes: &[u8]) -> bool {
+        let (data, trailer) = bytes.split_at(bytes.len() - self.trailer_len());
+        let expected = trailer.iter().fold(0u16, |v, &b| (v << 8) | b as u16);
+        self == Checksum::None || self.compute(data) == expected
+    }
+}
+
+// Bytes per sample of the boards that predate 24-bit support.
+fn default_sample_width() -> usize {
+    2
+}
+
+/// Wire format of a transport, with whatever is needed to decode it. Samples
+/// are big-endian, `sample_width` bytes each, and signed unless `unsigned`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "transport", rename_all = "lowercase")]
+pub enum Protocol {
+    /// `C7 7C <counter> <channels × sample> [checksum] 01` packets on a
+    /// byte stream.
+    Serial {
+        packet_size: usize,
+        channels: usize,
+        #[serde(default = "default_sample_width")]
+        sample_width: usize,
+        #[serde(default)]
+        checksum: Checksum,
+        /// Offset binary from the unipolar ADCs of the microcontroller
+        /// boards, which fill all 16 bits on the GIGA R1.
+        #[serde(default)]
+        unsigned: bool,
+    },
+    /// WebSocket binary frames holding 13-byte blocks.
+    Wifi {
+        #[serde(default = "default_sample_width")]
+        sample_width: usize,
+    },
+    /// BLE notifications holding one or ten samples of 3 channels.
+    Ble {
+        #[serde(default = "default_sample_width")]
+        sample_width: usize,
+    },
+}
+
+impl Protocol {
+    pub fn decoder(&self) -> Decoder {
+        let (kind, channels, sample_width, signed) = match *self {
+            Protocol::Serial {
+                packet_size,
+                channels,
+                sample_width,
+                checksum,
+                unsigned,
+            } => (
+                Kind::Serial {
+                    packet_size,
+                    checksum,
+                    ring: ByteRing::new((2 * packet_size).max(MIN_RING_LEN)),
+                    packet: vec![0; packet_size],
+                },
+                channels,
+                sample_width,
+                !unsigned,
+            ),
+            Protocol::Wifi { sample_width } => (Kind::Wifi, WIFI_CHANNELS, sample_width, true),
+            Protocol::Ble { sample_width } => (Kind::Ble, BLE_CHANNELS, sample_width, true),
+        };
+        Decoder {
+            kind,
+            sample_width,
+            signed,
+            sequence: SequenceTracker::default(),
+            sample: vec![0; channels],
+        }
+    }
+}
+
+enum Kind {
+    Serial {
+        packet_size: usize,
+        checksum: Checksum,
+        /// Bytes not decoded yet.
+        ring: ByteRing,
+        /// The packet being checked, copied out of the ring in one piece.
+        packet: Vec<u8>,
+    },
+    Wifi,
+    Ble,
+}
+
+/// Fixed-capacity FIFO of bytes, wrapping around instead of shifting.
+struct ByteRing {
+    bytes: Box<[u8]>,
+    /// Index of the oldest byte.
+    head: usize,
+    len: usize,
+}
+
+impl ByteRing {
+    fn new(capacity: usize) -> Self {
+        ByteRing {
+            bytes: vec![0; capacity].into_boxed_slice(),
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn free(&self) -> usize {
+        self.bytes.len() - self.len
+    }
+
+    /// The queued bytes, oldest first, in up to two pieces.
+    fn as_slices(&self) -> (&[u8], &[u8]) {
+        let end = self.head + self.len;
+        if end <= self.bytes.len() {
+            (&self.bytes[self.head..end], &[])
+        } else {
+            (
+                &self.bytes[self.head..],
+                &self.bytes[..end - self.bytes.len()],
+            )
+        }
+    }
+
+    fn get(&self, i: usize) -> u8 {
+        self.bytes[(self.head + i) % self.bytes.len()]
+    }
+
+    /// Appends as many of `bytes` as fit and returns how many that was.
+    fn push(&mut self, bytes: &[u8]) -> usize {
+        let count = bytes.len().min(self.free());
+        let tail = (self.head + self.len) % self.bytes.len();
+        let first = count.min(self.bytes.len() - tail);
+        self.bytes[tail..tail + first].copy_from_slice(&bytes[..first]);
+        self.bytes[..count - first].copy_from_slice(&bytes[first..count]);
+        self.len += count;
+        count
+    }
+
+    /// Copies the oldest `out.len()` bytes into `out`.
+    fn copy_to(&self, out: &mut [u8]) {
+        let (a, b) = self.as_slices();
+        let (head, rest) = out.split_at_mut(out.len().min(a.len()));
+        head.copy_from_slice(&a[..head.len()]);
+        rest.copy_from_slice(&b[..rest.len()]);
+    }
+
+    fn consume(&mut self, count: usize) {
+        let count = count.min(self.len);
+        self.head = (self.head + count) % self.bytes.len();
+        self.len -= count;
+    }
+
+    /// Position of the first `byte` from `from` on.
+    fn find(&self, byte: u8, from: usize) -> Option<usize> {
+        let (a, b) = self.as_slices();
+        if from < a.len() {
+            if let Some(i) = memchr::memchr(byte, &a[from..]) {
+                return Some(from + i);
+            }
+        }
+        let skip = from.saturating_sub(a.len());
+        memchr::memchr(byte, b.get(skip..)?).map(|i| a.len() + skip + i)
+    }
+}
+
+/// Turns raw transport bytes into samples of ADC counts. The live transports
+/// and the replay of a capture share it, so both decode exactly alike.
+pub struct Decoder {
+    kind: Kind,
+    sample_width: usize,
+    signed: bool,
+    sequence: SequenceTracker,
+    /// Reused for every sample, so decoding doesn't allocate.
+    sample: Vec<i32>,
+}
+
+impl Decoder {
+    /// Decodes one read, frame or notification, handing each sample to
+    /// `on_sample`. Lost, duplicate and corrupt packets are counted in `stats`;
+    /// duplicates are dropped.
+    pub fn feed(&mut self, bytes: &[u8], stats: &StreamStats, mut on_sample: impl FnMut(&[i32])) {
+        stats.add_bytes(bytes.len());
+        let (width, signed) = (self.sample_width, self.signed);
+        let sample = &mut self.sample[..];
+        match &mut self.kind {
+            Kind::Serial {
+                packet_size,
+                checksum,
+                ring,
+                packet,
+            } => {
+                let (packet_size, checksum) = (*packet_size, *checksum);
+                let mut bytes = bytes;
+                // The ring holds at least two packets, and less than one is
+                // left after decoding, so every pass takes some bytes.
+                while !bytes.is_empty() {
+                    bytes = &bytes[ring.push(bytes)..];
+
+                    while ring.len() >= packet_size {
+                        if ring.get(0) != START_BYTE_1 || ring.get(1) != START_BYTE_2 {
+                            // Skip straight to the next possible start of a packet.
+                            ring.consume(ring.find(START_BYTE_1, 1).unwrap_or(ring.len()));
+                            continue;
+                        }
+                        if ring.get(packet_size - 1) != END_BYTE {
+                            stats.add_corrupt();
+                            ring.consume(1);
+                            continue;
+                        }
+
+                        ring.copy_to(packet);
+                        // The sync and end bytes may have lined up by chance, as
+                        // with a bad end byte; the real packet can start inside.
+                        if !checksum.verify(&packet[2..packet_size - 1]) {
+                            stats.add_corrupt();
+                            ring.consume(1);
+                            continue;
+                        }
+                        ring.consume(packet_size);
+                        if Self::check(&mut self.sequence, packet[2], stats) {
+                            decode_be(&packet[3..], width, signed, sample);
+                            on_sample(sample);
+                        }
+                    }
+                }
+            }
+            Kind::Wifi => {
+                if bytes.len() % WIFI_BLOCK_LEN != 0 {
+                    stats.add_corrupt();
+                }
+                for block in bytes.chunks_exact(WIFI_BLOCK_LEN) {
+                    if Self::check(&mut self.sequence, block[0], stats) {
+                        decode_be(&block[1..], width, signed, sample);
+                        on_sample(sample);
+                    }
+                }
+            }
+            Kind::Ble => {
+                let sample_len = 1 + BLE_CHANNELS * width;
+                if bytes.len() != sample_len && bytes.len() != sample_len * BLE_BLOCK_COUNT {
+                    println!("[WARN] Unexpected packet length: {}", bytes.len());
+                    stats.add_corrupt();
+                    return;
+                }
+                for chunk in bytes.chunks_exact(sample_len) {
+                    if Self::check(&mut self.sequence, chunk[0], stats) {
+                        decode_be(&chunk[1..], width, signed, sample);
+                        on_sample(sample);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Checks a packet's counter; returns whether the packet should be used.
+    fn check(sequence: &mut SequenceTracker, counter: u8, stats: &StreamStats) -> bool {
+        let check = sequence.check(counter);
+        stats.add_sequence(check);
+        match check {
+            Sequence::Duplicate => {
+                println!("Error: Duplicate Sample");
+                return false;
+            }
+            Sequence::Lost(samples) => println!("Error: {} Samples Lost", samples),
+            Sequence::Next => {}
+        }
+        stats.add_received();
+        true
+    }
+}
+
+/// Reads big-endian samples of `width` bytes into `out`, one per channel,
+/// sign-extending them if `signed`.
+fn decode_be(bytes: &[u8], width: usize, signed: bool, out: &mut [i32]) {
+    let shift = 32 - 8 * width as u32;
+    for (value, sample) in out.iter_mut().zip(bytes.chunks_exact(width)) {
+        let raw = sample.iter().fold(0u32, |v, &b| (v << 8) | b as u32);
+        *value = if signed {
+            (raw << shift) as i32 >> shift
+        } else {
+            raw as i32
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::{StatsReport, StatsSampler};
+
+    const CHANNELS: usize = 2;
+
+    fn serial(checksum: Checksum, unsigned: bool) -> Protocol {
+        Protocol::Serial {
+            packet_size: 3 + CHANNELS * 2 + checksum.trailer_len() + 1,
+            channels: CHANNELS,
+            sample_width: 2,
+            checksum,
+            unsigned,
+        }
+    }
+
+    fn packet(counter: u8, values: [u16; CHANNELS], checksum: Checksum) -> Vec<u8> {
+        let mut packet = vec![START_BYTE_1, START_BYTE_2, counter];
+        for value in values {
+            packet.extend_from_slice(&value.to_be_bytes());
+        }
+        let trailer = checksum.compute(&packet[2..]).to_be_bytes();
+        packet.extend_from_slice(&trailer[2 - checksum.trailer_len()..]);
+        packet.push(END_BYTE);
+        packet
+    }
+
+    fn decode(protocol: &Protocol, reads: &[&[u8]]) -> (Vec<Vec<i32>>, StatsReport) {
+        let stats = StreamStats::default();
+        let mut decoder = protocol.decoder();
+        let mut samples = Vec::new();
+        for read in reads {
+            decoder.feed(read, &stats, |sample| samples.push(sample.to_vec()));
+        }
+        (samples, StatsSampler::new("test", 500.0).report(&stats))
+    }
+
+    #[test]
+    fn decodes_signed_samples() {
+        let bytes = [
+            packet(0, [1, 0xFFFE], Checksum::None),
+            packet(1, [3, 4], Checksum::None),
+        ]
+        .concat();
+        let (samples, report) = decode(&serial(Checksum::None, false), &[&bytes]);
+        assert_eq!(samples, vec![vec![1, -2], vec![3, 4]]);
+        assert_eq!((report.corrupt, report.lost), (0, 0));
+    }
+
+    #[test]
+    fn decodes_unsigned_samples() {
+        let bytes = packet(0, [0xFFFF, 0x8000], Checksum::None);
+        let (samples, _) = decode(&serial(Checksum::None, true), &[&bytes]);
+        assert_eq!(samples, vec![vec![65535, 32768]]);
+    }
+
+    #[test]
+    fn resyncs_after_garbage() {
+        let bytes = [
+            vec![0x00, START_BYTE_1, 0x13, 0xFF],
+            packet(0, [5, 6], Checksum::None),
+        ]
+        .concat();
+        let (samples, report) = decode(&serial(Checksum::None, false), &[&bytes]);
+        assert_eq!(samples, vec![vec![5, 6]]);
+        assert_eq!(report.corrupt, 0);
+    }
+
+    #[test]
+    fn skips_a_packet_with_a_bad_end_byte() {
+        let mut bad = packet(0, [1, 2], Checksum::None);
+        *bad.last_mut().unwrap() = 0x55;
+        let bytes = [bad, packet(1, [3, 4], Checksum::None)].concat();
+        let (samples, report) = decode(&serial(Checksum::None, false), &[&bytes]);
+        assert_eq!(samples, vec![vec![3, 4]]);
+        assert_eq!(report.corrupt, 1);
+    }
+
+    #[test]
+    fn recovers_from_a_short_packet() {
+        let short = &packet(0, [1, 2], Checksum::None)[..4];
+        let bytes = [short, &packet(1, [3, 4], Checksum::None)].concat();
+        let (samples, report) = decode(&serial(Checksum::None, false), &[&bytes]);
+        assert_eq!(samples, vec![vec![3, 4]]);
+        assert_eq!(report.corrupt, 1);
+    }
+
+    #[test]
+    fn joins_packets_split_across_reads() {
+        let bytes: Vec<u8> = (0..4)
+            .flat_map(|i| packet(i, [i as u16, 100], Checksum::Crc16))
+            .collect();
+        let reads: Vec<&[u8]> = bytes.chunks(3).collect();
+        let (samples, report) = decode(&serial(Checksum::Crc16, false), &reads);
+        assert_eq!(samples, (0..4).map(|i| vec![i, 100]).collect::<Vec<_>>());
+        assert_eq!((report.corrupt, report.lost), (0, 0));
+    }
+
+    #[test]
+    fn decodes_reads_longer_than_the_ring() {
+        // Enough packets to wrap the ring several times, with garbage
+        // between some so packets also straddle its end.
+        let bytes: Vec<u8> = (0..2000u32)
+            .flat_map(|i| {
+                let garbage = if i % 7 == 0 {
+                    &[0x42, START_BYTE_1][..]
+                } else {
+                    &[]
+                };
+                [garbage, &packet(i as u8, [i as u16, 1], Checksum::Crc8)].concat()
+            })
+            .collect();
+        assert!(bytes.len() > 3 * MIN_RING_LEN);
+        let expected: Vec<Vec<i32>> = (0..2000).map(|i| vec![i, 1]).collect();
+        for read_len in [bytes.len(), 1000, 13] {
+            let reads: Vec<&[u8]> = bytes.chunks(read_len).collect();
+            let (samples, report) = decode(&serial(Checksum::Crc8, false), &reads);
+            assert_eq!(samples, expected);
+            assert_eq!((report.corrupt, report.lost), (0, 0));
+        }
+    }
+
+    #[test]
+    fn ring_wraps_around() {
+        let mut ring = ByteRing::new(8);
+        assert_eq!(ring.push(&[1, 2, 3, 4, 5, 6]), 6);
+        ring.consume(5);
+        assert_eq!(ring.push(&[7, 8, 9, 10, 11, 12, 13, 14]), 7);
+        assert_eq!(ring.as_slices(), (&[6, 7, 8][..], &[9, 10, 11, 12, 13][..]));
+        assert_eq!(ring.find(6, 0), Some(0));
+        assert_eq!(ring.find(12, 1), Some(6));
+        assert_eq!(ring.find(7, 2), None);
+        let mut out = [0; 5];
+        ring.copy_to(&mut out);
+        assert_eq!(out, [6, 7, 8, 9, 10]);
+        assert_eq!(ring.get(7), 13);
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_packet() {
+        let bytes = packet(0, [7, 8], Checksum::None);
+        let stats = StreamStats::default();
+        let mut decoder = serial(Checksum::None, false).decoder();
+        let mut samples = Vec::new();
+        decoder.feed(&bytes[..4], &stats, |sample| samples.push(sample.to_vec()));
+        assert!(samples.is_empty());
+        decoder.feed(&bytes[4..], &stats, |sample| samples.push(sample.to_vec()));
+        assert_eq!(samples, vec![vec![7, 8]]);
+    }
+
+    #[test]
+    fn crc_check_values() {
+        assert_eq!(Checksum::Crc8.compute(b"123456789"), 0xF4);
+        assert_eq!(Checksum::Crc16.compute(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn skips_a_packet_with_a_bad_crc() {
+        for checksum in [Checksum::Crc8, Checksum::Crc16] {
+            let mut bad = packet(0, [1, 2], checksum);
+            bad[3] ^= 0x40;
+            let bytes = [bad, packet(1, [3, 4], checksum)].concat();
+            let (samples, report) = decode(&serial(checksum, false), &[&bytes]);
+            assert_eq!(samples, vec![vec![3, 4]]);
+            assert_eq!(report.corrupt, 1);
+        }
+    }
+
+    #[test]
+    fn finds_a_packet_inside_a_false_frame() {
+        // The real packet's first value puts an end byte right where garbage
+        // starting with sync bytes would end, so the garbage frames like a
+        // packet; skipping all of it would lose the real one.
+        let real = packet(0, [0x0100, 4], Checksum::Crc8);
+        let bytes = [&[START_BYTE_1, START_BYTE_2, 0x10, 0x20, 0x30][..], &real].concat();
+        assert_eq!(bytes[real.len() - 1], END_BYTE);
+        let (samples, report) = decode(&serial(Checksum::Crc8, false), &[&bytes]);
+        assert_eq!(samples, vec![vec![0x0100, 4]]);
+        assert_eq!(report.corrupt, 1);
+    }
+
+    #[test]
+    fn counts_lost_samples() {
+        let bytes = [
+            packet(0, [1, 1], Checksum::None),
+            packet(3, [2, 2], Checksum::None),
+        ]
+        .concat();
+        let (samples, report) = decode(&serial(Checksum::None, false), &[&bytes]);
+        assert_eq!(samples.len(), 2);
+        assert_eq!(report.lost, 2);
+    }
+}
@@ -0,0 +1,291 @@
+use crate::board::BoardProfile;
+use crate::handshake::Handshake;
+use serde::Serialize;
+use serialport::{ClearBuffer, SerialPortInfo, SerialPortType};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+// Short reads during the handshake, so its timeouts hold.
+pub const HANDSHAKE_READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Baud rates tried on each port unless the caller gives its own, in order.
+pub const DEFAULT_BAUD_RATES: [u32; 2] = [230400, 115200];
+/// Sampling rate of legacy firmware that doesn't report its own.
+pub const DEFAULT_SAMPLE_RATE: f64 = 500.0;
+// Boards behind the CH340 and similar bridges of the clones run slower, and
+// their firmware samples at a lower rate to fit.
+const CLONE_BAUD_RATE: u32 = 115200;
+const CLONE_SAMPLE_RATE: f64 = 250.0;
+// Identify attempts per baud rate when there are several to try; a wrong
+// rate only ever returns garbage.
+const ATTEMPTS_PER_RATE: u32 = 2;
+
+// Bumped by every detection and cancel; a detection runs while it holds the
+// latest value.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+// The same for probes of a single port, which leave a full detection running
+// alongside them alone.
+static PORT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// A board found by `detect_arduino`.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DetectedBoard {
+    pub port: String,
+    pub baud_rate: u32,
+    pub board: BoardProfile,
+}
+
+/// A serial port as `list_serial_ports` reports it. The USB fields are
+/// `None` for ports that aren't behind a USB bridge.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SerialPortEntry {
+    pub port: String,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub serial_number: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum ProbeStatus {
+    Probing,
+    Found,
+    NotFound,
+    Cancelled,
+}
+
+/// Payload of the `detection` event.
+#[derive(Clone, Serialize)]
+struct ProbeEvent<'a> {
+    port: &'a str,
+    status: ProbeStatus,
+    /// Name of the board, once found.
+    board: Option<&'a str>,
+}
+
+/// Probes every candidate port at once, at each of `baud_rates`, and returns
+/// the boards found, in port order.
+pub fn detect(app_handle: &AppHandle, baud_rates: &[u32]) -> Result<Vec<DetectedBoard>, String> {
+    if baud_rates.is_empty() || baud_rates.contains(&0) {
+        return Err("Baud rates must be positive".to_string());
+    }
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    let ports = serialport::available_ports().map_err(|e| e.to_string())?;
+    let candidates: Vec<SerialPortInfo> = ports
+        .into_iter()
+        .filter(|port| {
+            !(port.port_name.contains("BLTH")
+                || port.port_name.contains("Bluetooth")
+                || port.port_name.contains("console"))
+        })
+        .collect();
+
+    let found: Vec<DetectedBoard> = thread::scope(|scope| {
+        let probes: Vec<_> = candidates
+            .iter()
+            .map(|port| {
+                let rates = bridge_rates(port, baud_rates);
+                let port_name = port.port_name.as_str();
+                scope.spawn(move || {
+                    probe(port_name, &rates, &GENERATION, generation, app_handle).ok()
+                })
+            })
+            .collect();
+        probes
+            .into_iter()
+            .filter_map(|probe| probe.join().ok().flatten())
+            .collect()
+    });
+
+    if GENERATION.load(Ordering::SeqCst) != generation {
+        return Err("Detection cancelled".to_string());
+    }
+    if found.is_empty() {
+        return Err("No device found".to_string());
+    }
+    Ok(found)
+}
+
+/// Every serial port on the system, including those detection skips.
+pub fn list_ports() -> Result<Vec<SerialPortEntry>, String> {
+    let ports = serialport::available_ports().map_err(|e| e.to_string())?;
+    Ok(ports
+        .into_iter()
+        .map(|port| match port.port_type {
+            SerialPortType::UsbPort(info) => SerialPortEntry {
+                port: port.port_name,
+                vid: Some(info.vid),
+                pid: Some(info.pid),
+                serial_number: info.serial_number,
+                manufacturer: info.manufacturer,
+                product: info.product,
+            },
+            _ => SerialPortEntry {
+                port: port.port_name,
+                vid: None,
+                pid: None,
+                serial_number: None,
+                manufacturer: None,
+                product: None,
+            },
+        })
+        .collect())
+}
+
+/// USB serial number of the bridge behind `port_name`.
+pub fn serial_number(port_name: &str) -> Option<String> {
+    list_ports()
+        .ok()?
+        .into_iter()
+        .find(|port| port.port == port_name)?
+        .serial_number
+}
+
+/// The port the USB bridge with `serial_number` currently shows up as, which
+/// can change when it re-enumerates.
+pub fn port_with_serial(serial_number: &str) -> Option<String> {
+    list_ports()
+        .ok()?
+        .into_iter()
+        .find(|port| port.serial_number.as_deref() == Some(serial_number))
+        .map(|port| port.port)
+}
+
+/// Probes only `port_name` at `baud_rate`, for reconnecting to a board known
+/// from before.
+pub fn detect_on(
+    port_name: &str,
+    baud_rate: u32,
+    app_handle: &AppHandle,
+) -> Result<DetectedBoard, String> {
+    let generation = PORT_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    probe(
+        port_name,
+        &[baud_rate],
+        &PORT_GENERATION,
+        generation,
+        app_handle,
+    )
+}
+
+/// Stops a running detection and any probe of a single port.
+pub fn cancel() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+    PORT_GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+// `baud_rates` in the order to try them on `port`: the clones' rate first
+// when the board is behind one of their USB bridges.
+fn bridge_rates(port: &SerialPortInfo, baud_rates: &[u32]) -> Vec<u32> {
+    let clone = match &port.port_type {
+        SerialPortType::UsbPort(info) => {
+            info.vid == 6790 || matches!(info.pid, 67 | 579 | 29987 | 66 | 24577)
+        }
+        _ => false,
+    };
+    let mut rates = baud_rates.to_vec();
+    if clone {
+        rates.sort_by_key(|&rate| rate != CLONE_BAUD_RATE);
+    }
+    rates
+}
+
+/// Sampling rate of legacy firmware talking at `baud_rate`.
+pub fn legacy_sample_rate(baud_rate: u32) -> f64 {
+    if baud_rate <= CLONE_BAUD_RATE {
+        CLONE_SAMPLE_RATE
+    } else {
+        DEFAULT_SAMPLE_RATE
+    }
+}
+
+// Identifies the board on `port_name`, trying each of `baud_rates` until the
+// board answers or `token` moves on from `generation`.
+fn probe(
+    port_name: &str,
+    baud_rates: &[u32],
+    token: &'static AtomicU64,
+    generation: u64,
+    app_handle: &AppHandle,
+) -> Result<DetectedBoard, String> {
+    let cancelled = move || token.load(Ordering::SeqCst) != generation;
+    let report = |status, board| {
+        let _ = app_handle.emit(
+            "detection",
+            ProbeEvent {
+                port: port_name,
+                status,
+                board,
+            },
+        );
+    };
+    println!("Attempting to connect to port: {}", port_name);
+    report(ProbeStatus::Probing, None);
+
+    let mut serial = match serialport::new(port_name, baud_rates[0])
+        .timeout(HANDSHAKE_READ_TIMEOUT)
+        .open()
+    {
+        Ok(serial) => serial,
+        Err(e) => {
+            println!("Failed to open port: {}. Error: {:?}", port_name, e);
+            report(ProbeStatus::NotFound, None);
+            return Err(format!("Failed to open port {}: {}", port_name, e));
+        }
+    };
+
+    let mut handshake = Handshake::new(port_name, app_handle).cancellable(cancelled);
+    if baud_rates.len() > 1 {
+        handshake = handshake.identify_attempts(ATTEMPTS_PER_RATE);
+    }
+    // Opening the port resets most boards; once they have booted, switching
+    // the rate doesn't.
+    let mut error = handshake.reset_wait(&mut *serial).err();
+    if error.is_none() {
+        for &baud_rate in baud_rates {
+            println!("Trying {} at {} baud", port_name, baud_rate);
+            let identified = serial
+                .set_baud_rate(baud_rate)
+                .and_then(|_| serial.clear(ClearBuffer::Input))
+                .map_err(|e| e.to_string())
+                .and_then(|_| handshake.identify(&mut *serial));
+            match identified {
+                Ok(mut board) => {
+                    println!(
+                        "{} found on port: {} at {} baud",
+                        board.name, port_name, baud_rate
+                    );
+                    report(ProbeStatus::Found, Some(&board.name));
+                    board
+                        .sample_rate
+                        .get_or_insert(legacy_sample_rate(baud_rate));
+                    return Ok(DetectedBoard {
+                        port: port_name.to_string(),
+                        baud_rate,
+                        board,
+                    });
+                }
+                Err(e) => {
+                    println!("{}", e);
+                    error = Some(e);
+                    if cancelled() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    if cancelled() {
+        report(ProbeStatus::Cancelled, None);
+    } else {
+        report(ProbeStatus::NotFound, None);
+    }
+    Err(error.unwrap_or_default())
+}
@@ -0,0 +1,266 @@
+use crate::filter::Biquad;
+use crate::session::select_channels;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::f64::consts::FRAC_1_SQRT_2;
+
+/// Settings of the heart-rate detector.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EcgConfig {
+    /// 1-based channel carrying the ECG.
+    pub channel: usize,
+}
+
+impl EcgConfig {
+    /// Fails if a stream of `channels` doesn't have the ECG channel.
+    pub fn validate(&self, channels: usize) -> Result<(), String> {
+        select_channels(&[self.channel], channels).map(|_| ())
+    }
+}
+
+/// A detected R peak.
+#[derive(Clone, Copy, Debug)]
+pub struct Beat {
+    /// How many samples ago the R peak happened.
+    pub delay: usize,
+    /// Interval to the previous R peak in seconds, once there is one.
+    pub rr: Option<f64>,
+}
+
+impl Beat {
+    pub fn bpm(&self) -> Option<f64> {
+        self.rr.map(|rr| 60.0 / rr)
+    }
+}
+
+/// Streaming Pan-Tompkins QRS detector: band-pass, derivative, squaring and
+/// moving-window integration, followed by adaptive thresholds with searchback.
+pub struct QrsDetector {
+    sample_rate: f64,
+    band: [(Biquad, [f64; 2]); 2],
+    derivative: [f64; 4],
+    window: usize,
+    integrator: VecDeque<f64>,
+    integral: f64,
+    // Band-passed signal, used to place the R peak inside the integration window.
+    recent: VecDeque<f64>,
+    recent_len: usize,
+
+    // Previous two integrator outputs, for local maximum detection.
+    previous: [f64; 2],
+    samples: usize,
+    learning: usize,
+    learning_max: f64,
+    learning_sum: f64,
+
+    signal_peak: f64,
+    noise_peak: f64,
+    refractory: usize,
+    last_beat: Option<usize>,
+    rr_average: Option<f64>,
+    // Largest sub-threshold peak since the last beat, for searchback.
+    candidate: Option<(usize, f64)>,
+}
+
+impl QrsDetector {
+    pub fn new(sample_rate: f64) -> Self {
+        let window = ((0.150 * sample_rate) as usize).max(1);
+        QrsDetector {
+            sample_rate,
+            band: [
+                (Biquad::highpass(5.0, sample_rate, FRAC_1_SQRT_2), [0.0; 2]),
+                (Biquad::lowpass(15.0, sample_rate, FRAC_1_SQRT_2), [0.0; 2]),
+            ],
+            derivative: [0.0; 4],
+            window,
+            integrator: VecDeque::with_capacity(window),
+            integral: 0.0,
+            recent: VecDeque::with_capacity(2 * window),
+            recent_len: 2 * window,
+            previous: [0.0; 2],
+            samples: 0,
+            learning: (2.0 * sample_rate) as usize,
+            learning_max: 0.0,
+            learning_sum: 0.0,
+            signal_peak: 0.0,
+            noise_peak: 0.0,
+            refractory: (0.200 * sample_rate) as usize,
+            last_beat: None,
+            rr_average: None,
+            candidate: None,
+        }
+    }
+
+    pub fn push(&mut self, x: f64) -> Option<Beat> {
+        let mut y = x;
+        for (section, z) in self.band.iter_mut() {
+            y = section.run(y, z);
+        }
+
+        if self.recent.len() == self.recent_len {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(y);
+
+        // Five-point derivative, then squaring.
+        let d = &mut self.derivative;
+        let slope = (2.0 * y + d[0] - d[2] - 2.0 * d[3]) * self.sample_rate / 8.0;
+        d.rotate_right(1);
+        d[0] = y;
+        let squared = slope * slope;
+
+        if self.integrator.len() == self.window {
+            self.integral -= self.integrator.pop_front().unwrap_or(0.0);
+        }
+        self.integrator.push_back(squared);
+        self.integral += squared;
+        let mwi = self.integral / self.integrator.len() as f64;
+
+        let now = self.samples;
+        self.samples += 1;
+
+        if now < self.learning {
+            self.learning_max = self.learning_max.max(mwi);
+            self.learning_sum += mwi;
+            if now + 1 == self.learning {
+                self.signal_peak = self.learning_max / 3.0;
+                self.noise_peak = self.learning_sum / self.learning as f64 / 2.0;
+            }
+            self.previous = [self.previous[1], mwi];
+            return None;
+        }
+
+        let [before, peak] = self.previous;
+        self.previous = [peak, mwi];
+        let is_peak = peak > before && peak >= mwi;
+
+        let threshold = self.noise_peak + 0.25 * (self.signal_peak - self.noise_peak);
+        let since_beat = self.last_beat.map(|last| now - last);
+
+        if is_peak && since_beat.map_or(true, |since| since > self.refractory) {
+            if peak > threshold {
+                self.signal_peak = 0.125 * peak + 0.875 * self.signal_peak;
+                let r_peak = self.locate_r_peak();
+                return Some(self.beat(r_peak));
+            }
+            self.noise_peak = 0.125 * peak + 0.875 * self.noise_peak;
+            if self.candidate.map_or(true, |(_, value)| peak > value) {
+                self.candidate = Some((now - 1, peak));
+            }
+        }
+
+        // Searchback: a beat is overdue, so take the best peak above half the threshold.
+        if let (Some(since), Some(rr)) = (since_beat, self.rr_average) {
+            if since as f64 > 1.66 * rr * self.sample_rate {
+                if let Some((at, value)) = self.candidate.take() {
+                    if value > 0.5 * threshold {
+                        self.signal_peak = 0.25 * value + 0.75 * self.signal_peak;
+                        // The band-passed history no longer covers it, so assume the
+                        // usual lag of half an integration window.
+                        return Some(self.beat(at.saturating_sub(self.window / 2)));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Sample index of the largest band-passed deflection in the recent window.
+    fn locate_r_peak(&self) -> usize {
+        let delay = self
+            .recent
+            .iter()
+            .rev()
+            .enumerate()
+            .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+            .map(|(i, _)| i)
+            .unwrap_or(0);
+        (self.samples - 1).saturating_sub(delay)
+    }
+
+    fn beat(&mut self, r_peak: usize) -> Beat {
+        self.candidate = None;
+
+        let rr = self
+            .last_beat
+            .map(|last| r_peak.saturating_sub(last) as f64 / self.sample_rate)
+            .filter(|rr| *rr > 0.0);
+        if let Some(rr) = rr {
+            self.rr_average = Some(match self.rr_average {
+                Some(average) => 0.875 * average + 0.125 * rr,
+                None => rr,
+            });
+        }
+        self.last_beat = Some(r_peak);
+
+        Beat {
+            delay: self.samples - 1 - r_peak,
+            rr,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const RATE: f64 = 250.0;
+
+    /// An ECG-like trace at `bpm`: a narrow R wave, a broad T wave and some
+    /// baseline wander.
+    fn ecg(bpm: f64, seconds: f64) -> (Vec<f64>, Vec<usize>) {
+        let period = 60.0 / bpm;
+        let r_peaks: Vec<usize> = (1..)
+            .map(|i| (i as f64 * period * RATE).round() as usize)
+            .take_while(|&at| (at as f64) < seconds * RATE)
+            .collect();
+        let signal = (0..(seconds * RATE) as usize)
+            .map(|n| {
+                let t = n as f64 / RATE;
+                let r = r_peaks
+                    .iter()
+                    .map(|&at| {
+                        let dt = t - at as f64 / RATE;
+                        (-(dt / 0.012).powi(2)).exp() + 0.3 * (-((dt - 0.25) / 0.05).powi(2)).exp()
+                    })
+                    .sum::<f64>();
+                r + 0.1 * (2.0 * PI * 0.3 * t).sin()
+            })
+            .collect();
+        (signal, r_peaks)
+    }
+
+    #[test]
+    fn finds_every_r_peak_at_a_steady_rate() {
+        let (signal, r_peaks) = ecg(72.0, 30.0);
+        let mut detector = QrsDetector::new(RATE);
+        let mut beats = Vec::new();
+        for (n, &x) in signal.iter().enumerate() {
+            if let Some(beat) = detector.push(x) {
+                beats.push((n - beat.delay, beat));
+            }
+        }
+
+        // Nothing is detected while the thresholds are learnt.
+        let expected: Vec<usize> = r_peaks
+            .into_iter()
+            .filter(|&at| at > 2 * RATE as usize)
+            .collect();
+        assert_eq!(beats.len(), expected.len());
+        for ((at, _), r_peak) in beats.iter().zip(&expected) {
+            assert!(
+                at.abs_diff(*r_peak) <= 2,
+                "beat at {} for R peak at {}",
+                at,
+                r_peak
+            );
+        }
+        for (_, beat) in &beats[1..] {
+            let bpm = beat.bpm().unwrap();
+            assert!((bpm - 72.0).abs() < 1.0, "{} bpm", bpm);
+        }
+    }
+}
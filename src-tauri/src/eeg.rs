@@ -0,0 +1,250 @@
+use crate::session::select_channels;
+use crate::spectrum::{band_power, Welch};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// A named frequency band.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Band {
+    pub name: String,
+    pub low: f64,
+    pub high: f64,
+}
+
+impl Band {
+    fn new(name: &str, low: f64, high: f64) -> Self {
+        Band {
+            name: name.to_string(),
+            low,
+            high,
+        }
+    }
+}
+
+/// Settings of the EEG band-power stage.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BandPowerConfig {
+    /// 1-based channels to analyze; empty means all of them.
+    pub channels: Vec<usize>,
+    /// Electrode names such as "Fp1", indexed like the session's channels.
+    /// Channels without one are called "CH<n>".
+    pub labels: Vec<String>,
+    /// Seconds of signal each estimate is computed from.
+    pub window_secs: f64,
+    /// Estimates per second.
+    pub rate: f64,
+    pub bands: Vec<Band>,
+    /// Band power ratios to publish, as (numerator, denominator) band names.
+    pub ratios: Vec<(String, String)>,
+}
+
+impl Default for BandPowerConfig {
+    fn default() -> Self {
+        BandPowerConfig {
+            channels: Vec::new(),
+            labels: Vec::new(),
+            window_secs: 2.0,
+            rate: 4.0,
+            bands: vec![
+                Band::new("delta", 1.0, 4.0),
+                Band::new("theta", 4.0, 8.0),
+                Band::new("alpha", 8.0, 13.0),
+                Band::new("beta", 13.0, 30.0),
+                Band::new("gamma", 30.0, 45.0),
+            ],
+            ratios: vec![
+                ("alpha".to_string(), "beta".to_string()),
+                ("theta".to_string(), "beta".to_string()),
+            ],
+        }
+    }
+}
+
+/// Windowed band power of the selected channels, estimated with Welch's method.
+pub struct BandPower {
+    config: BandPowerConfig,
+    channels: Vec<usize>,
+    ratios: Vec<(usize, usize)>,
+    welch: Welch,
+    history: Vec<VecDeque<f64>>,
+    capacity: usize,
+    interval: usize,
+    since_update: usize,
+    psd: Vec<f64>,
+}
+
+impl BandPower {
+    /// Fails if a selected channel doesn't exist, or the sampling rate or
+    /// window isn't positive.
+    pub fn new(config: BandPowerConfig, channels: usize, sample_rate: f64) -> Result<Self, String> {
+        if !(sample_rate.is_finite() && sample_rate > 0.0) {
+            return Err(format!("Invalid sampling rate of {} Hz", sample_rate));
+        }
+        if !(config.window_secs.is_finite() && config.window_secs > 0.0) {
+            return Err(format!(
+                "Band-power window of {} s must be positive",
+                config.window_secs
+            ));
+        }
+        let selected = select_channels(&config.channels, channels)?;
+        // Ratios naming an unknown band are dropped.
+        let position = |name: &str| config.bands.iter().position(|b| b.name == name);
+        let ratios = config
+            .ratios
+            .iter()
+            .filter_map(|(num, den)| Some((position(num)?, position(den)?)))
+            .collect();
+
+        let capacity = ((config.window_secs * sample_rate) as usize).max(2);
+        // One-second segments with 50% overlap, or the whole window if it's shorter.
+        let segment = capacity.min(sample_rate as usize);
+
+        Ok(BandPower {
+            welch: Welch::new(segment, 0.5, sample_rate),
+            history: vec![VecDeque::with_capacity(capacity); selected.len()],
+            channels: selected,
+            ratios,
+            capacity,
+            interval: ((sample_rate / config.rate.max(0.1)) as usize).max(1),
+            since_update: 0,
+            psd: Vec::new(),
+            config,
+        })
+    }
+
+    pub fn config(&self) -> &BandPowerConfig {
+        &self.config
+    }
+
+    /// 1-based numbers of the analyzed channels, in output order.
+    pub fn channels(&self) -> &[usize] {
+        &self.channels
+    }
+
+    /// Output labels, e.g. `Fp1_alpha` and `Fp1_alpha/beta`, in output order.
+    pub fn labels(&self) -> Vec<String> {
+        let mut labels = Vec::new();
+        for &channel in &self.channels {
+            let name = self
+                .config
+                .labels
+                .get(channel - 1)
+                .filter(|label| !label.is_empty())
+                .cloned()
+                .unwrap_or_else(|| format!("CH{}", channel));
+            for band in &self.config.bands {
+                labels.push(format!("{}_{}", name, band.name));
+            }
+            for &(num, den) in &self.ratios {
+                let (num, den) = (&self.config.bands[num].name, &self.config.bands[den].name);
+                labels.push(format!("{}_{}/{}", name, num, den));
+            }
+        }
+        labels
+    }
+
+    /// Adds one sample in microvolts. Returns the band powers in µV² and the
+    /// ratios, laid out as `labels`, whenever a new estimate is due.
+    pub fn push(&mut self, sample: &[f32]) -> Option<Vec<f32>> {
+        for (history, &channel) in self.history.iter_mut().zip(&self.channels) {
+            if history.len() == self.capacity {
+                history.pop_front();
+            }
+            history.push_back(sample[channel - 1] as f64);
+        }
+
+        self.since_update += 1;
+        let filled = self
+            .history
+            .first()
+            .is_some_and(|h| h.len() == self.capacity);
+        if self.since_update < self.interval || !filled {
+            return None;
+        }
+        self.since_update = 0;
+
+        let resolution = self.welch.resolution();
+        let mut values =
+            Vec::with_capacity(self.history.len() * (self.config.bands.len() + self.ratios.len()));
+        for history in &mut self.history {
+            self.welch.psd(history.make_contiguous(), &mut self.psd);
+            let powers: Vec<f64> = self
+                .config
+                .bands
+                .iter()
+                .map(|band| band_power(&self.psd, resolution, band.low, band.high))
+                .collect();
+            values.extend(powers.iter().map(|&p| p as f32));
+            for &(num, den) in &self.ratios {
+                let ratio = if powers[den] > 0.0 {
+                    powers[num] / powers[den]
+                } else {
+                    0.0
+                };
+                values.push(ratio as f32);
+            }
+        }
+        Some(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const RATE: f64 = 250.0;
+
+    #[test]
+    fn power_lands_in_the_band_of_the_tone() {
+        let config = BandPowerConfig {
+            labels: vec!["O1".to_string()],
+            ..Default::default()
+        };
+        let mut power = BandPower::new(config, 2, RATE).unwrap();
+        let labels = power.labels();
+        assert_eq!(labels.len(), 2 * 7);
+        assert_eq!(labels[2], "O1_alpha");
+        assert_eq!(labels[5], "O1_alpha/beta");
+        assert_eq!(labels[7 + 3], "CH2_beta");
+
+        let mut last = None;
+        for n in 0..(4.0 * RATE) as usize {
+            let t = n as f64 / RATE;
+            // 20 µV amplitude, so 200 µV² of power.
+            let alpha = 20.0 * (2.0 * PI * 10.0 * t).sin();
+            let beta = 20.0 * (2.0 * PI * 20.0 * t).sin();
+            last = power.push(&[alpha as f32, beta as f32]).or(last);
+        }
+        let values = last.expect("no estimate after two windows");
+        let (first, second) = values.split_at(7);
+
+        let peak = |powers: &[f32]| {
+            (0..5)
+                .max_by(|&a, &b| powers[a].total_cmp(&powers[b]))
+                .unwrap()
+        };
+        assert_eq!(peak(first), 2);
+        assert_eq!(peak(second), 3);
+        assert!((first[2] - 200.0).abs() < 20.0, "alpha power {}", first[2]);
+        assert!((second[3] - 200.0).abs() < 20.0, "beta power {}", second[3]);
+        assert!(first[5] > 100.0);
+        assert!(second[5] < 0.01);
+    }
+
+    #[test]
+    fn rejects_bad_settings() {
+        let missing = BandPowerConfig {
+            channels: vec![3],
+            ..Default::default()
+        };
+        assert!(BandPower::new(missing, 2, RATE).is_err());
+        let no_window = BandPowerConfig {
+            window_secs: 0.0,
+            ..Default::default()
+        };
+        assert!(BandPower::new(no_window, 2, RATE).is_err());
+        assert!(BandPower::new(BandPowerConfig::default(), 2, f64::NAN).is_err());
+    }
+}
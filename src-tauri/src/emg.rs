@@ -0,0 +1,260 @@
+use crate::filter::Biquad;
+use crate::session::select_channels;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::f64::consts::FRAC_1_SQRT_2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvelopeMethod {
+    /// Moving RMS over `window_ms`.
+    #[default]
+    Rms,
+    /// Full-wave rectification followed by a low-pass at `cutoff`.
+    Lowpass,
+}
+
+/// Settings of the EMG envelope and activation detector.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EmgConfig {
+    /// 1-based channels to process; empty means all of them.
+    pub channels: Vec<usize>,
+    pub method: EnvelopeMethod,
+    /// RMS window length in milliseconds.
+    pub window_ms: f64,
+    /// Low-pass cutoff in Hz for the rectified signal.
+    pub cutoff: f64,
+    /// High-pass applied before rectification to remove drift and motion artifacts.
+    pub highpass: f64,
+    /// Envelope level in microvolts at which a muscle counts as active.
+    pub onset: f64,
+    /// Level below which it is inactive again; defaults to half of `onset`.
+    pub offset: Option<f64>,
+    /// The level must be crossed for this long before the state changes.
+    pub hold_ms: f64,
+}
+
+impl Default for EmgConfig {
+    fn default() -> Self {
+        EmgConfig {
+            channels: Vec::new(),
+            method: EnvelopeMethod::Rms,
+            window_ms: 100.0,
+            cutoff: 5.0,
+            highpass: 20.0,
+            onset: 50.0,
+            offset: None,
+            hold_ms: 50.0,
+        }
+    }
+}
+
+/// An activation state change on one channel.
+#[derive(Clone, Copy, Debug)]
+pub struct Activation {
+    /// 1-based channel number.
+    pub channel: usize,
+    pub active: bool,
+}
+
+impl Activation {
+    pub fn marker(&self) -> String {
+        let edge = if self.active { "onset" } else { "offset" };
+        format!("CH{}_{}", self.channel, edge)
+    }
+}
+
+struct ChannelEnvelope {
+    channel: usize,
+    highpass: (Biquad, [f64; 2]),
+    lowpass: (Biquad, [f64; 2]),
+    squares: VecDeque<f64>,
+    sum: f64,
+    active: bool,
+    pending: usize,
+}
+
+/// Envelope and onset/offset detection for the selected channels of a session.
+pub struct EmgProcessor {
+    config: EmgConfig,
+    window: usize,
+    hold: usize,
+    channels: Vec<ChannelEnvelope>,
+}
+
+impl EmgProcessor {
+    /// Fails if a filter frequency isn't between zero and Nyquist, or a
+    /// selected channel doesn't exist.
+    pub fn new(config: EmgConfig, channels: usize, sample_rate: f64) -> Result<Self, String> {
+        let nyquist = sample_rate / 2.0;
+        for (name, freq) in [("high-pass", config.highpass), ("cutoff", config.cutoff)] {
+            if !(freq > 0.0 && freq < nyquist) {
+                return Err(format!(
+                    "EMG {} of {} Hz is outside 0 to {} Hz",
+                    name, freq, nyquist
+                ));
+            }
+        }
+        let selected = select_channels(&config.channels, channels)?;
+        let window = ((config.window_ms / 1000.0 * sample_rate) as usize).max(1);
+        let hold = (config.hold_ms / 1000.0 * sample_rate) as usize;
+        let highpass = Biquad::highpass(config.highpass, sample_rate, FRAC_1_SQRT_2);
+        let lowpass = Biquad::lowpass(config.cutoff, sample_rate, FRAC_1_SQRT_2);
+
+        Ok(EmgProcessor {
+            channels: selected
+                .into_iter()
+                .map(|channel| ChannelEnvelope {
+                    channel,
+                    highpass: (highpass, [0.0; 2]),
+                    lowpass: (lowpass, [0.0; 2]),
+                    squares: VecDeque::with_capacity(window),
+                    sum: 0.0,
+                    active: false,
+                    pending: 0,
+                })
+                .collect(),
+            config,
+            window,
+            hold,
+        })
+    }
+
+    pub fn config(&self) -> &EmgConfig {
+        &self.config
+    }
+
+    /// 1-based numbers of the processed channels, in output order.
+    pub fn channels(&self) -> impl Iterator<Item = usize> + '_ {
+        self.channels.iter().map(|c| c.channel)
+    }
+
+    /// Processes one sample in microvolts, writing one envelope value per
+    /// processed channel into `envelope` and any state changes into `activations`.
+    pub fn push(
+        &mut self,
+        sample: &[f32],
+        envelope: &mut Vec<f32>,
+        activations: &mut Vec<Activation>,
+    ) {
+        envelope.clear();
+        let onset = self.config.onset;
+        let offset = self.config.offset.unwrap_or(onset / 2.0);
+
+        for state in &mut self.channels {
+            let x = sample[state.channel - 1] as f64;
+            let (section, z) = &mut state.highpass;
+            let x = section.run(x, z);
+
+            let level = match self.config.method {
+                EnvelopeMethod::Rms => {
+                    if state.squares.len() == self.window {
+                        state.sum -= state.squares.pop_front().unwrap_or(0.0);
+                    }
+                    state.squares.push_back(x * x);
+                    state.sum += x * x;
+                    (state.sum.max(0.0) / state.squares.len() as f64).sqrt()
+                }
+                EnvelopeMethod::Lowpass => {
+                    let (section, z) = &mut state.lowpass;
+                    section.run(x.abs(), z).max(0.0)
+                }
+            };
+            envelope.push(level as f32);
+
+            let crossing = if state.active {
+                level < offset
+            } else {
+                level > onset
+            };
+            if crossing {
+                state.pending += 1;
+                if state.pending > self.hold {
+                    state.active = !state.active;
+                    state.pending = 0;
+                    activations.push(Activation {
+                        channel: state.channel,
+                        active: state.active,
+                    });
+                }
+            } else {
+                state.pending = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const RATE: f64 = 1000.0;
+
+    /// Time in seconds and activation of every state change, for a quiet
+    /// channel 1 and a 200 µV burst on channel 2 from 1 s to 2 s.
+    fn activations(method: EnvelopeMethod) -> Vec<(f64, Activation)> {
+        let config = EmgConfig {
+            method,
+            ..Default::default()
+        };
+        let mut processor = EmgProcessor::new(config, 2, RATE).unwrap();
+        let (mut envelope, mut changes) = (Vec::new(), Vec::new());
+        let mut found = Vec::new();
+        for n in 0..(3.0 * RATE) as usize {
+            let t = n as f64 / RATE;
+            let noise = 5.0 * (2.0 * PI * 80.0 * t).sin();
+            let burst = if (1.0..2.0).contains(&t) {
+                200.0 * (2.0 * PI * 100.0 * t).sin()
+            } else {
+                0.0
+            };
+            processor.push(
+                &[noise as f32, (noise + burst) as f32],
+                &mut envelope,
+                &mut changes,
+            );
+            assert_eq!(envelope.len(), 2);
+            found.extend(changes.drain(..).map(|change| (t, change)));
+        }
+        found
+    }
+
+    fn check_burst(found: &[(f64, Activation)]) {
+        assert_eq!(found.len(), 2, "{:?}", found);
+        let (on, onset) = found[0];
+        let (off, offset) = found[1];
+        assert_eq!((onset.channel, onset.active), (2, true));
+        assert_eq!((offset.channel, offset.active), (2, false));
+        // Not before the burst plus the hold, and not much later.
+        assert!((1.05..1.2).contains(&on), "onset at {}", on);
+        assert!((2.05..2.3).contains(&off), "offset at {}", off);
+        assert_eq!(onset.marker(), "CH2_onset");
+        assert_eq!(offset.marker(), "CH2_offset");
+    }
+
+    #[test]
+    fn rms_envelope_detects_onset_and_offset() {
+        check_burst(&activations(EnvelopeMethod::Rms));
+    }
+
+    #[test]
+    fn lowpass_envelope_detects_onset_and_offset() {
+        check_burst(&activations(EnvelopeMethod::Lowpass));
+    }
+
+    #[test]
+    fn rejects_bad_settings() {
+        let missing = EmgConfig {
+            channels: vec![3],
+            ..Default::default()
+        };
+        assert!(EmgProcessor::new(missing, 2, RATE).is_err());
+        let above_nyquist = EmgConfig {
+            highpass: RATE,
+            ..Default::default()
+        };
+        assert!(EmgProcessor::new(above_nyquist, 2, RATE).is_err());
+    }
+}
@@ -0,0 +1,38 @@
+use crossbeam_channel::{bounded, Sender, TrySendError};
+use serde::Serialize;
+use std::thread;
+use tauri::{AppHandle, Emitter};
+
+// Payloads waiting for the webview. Anything beyond this is dropped.
+const QUEUE_DEPTH: usize = 4;
+
+/// Emits high-rate events from a separate thread, so a slow webview can
+/// never block acquisition. Payloads are dropped when the queue is full.
+pub struct EventQueue<T> {
+    sender: Sender<T>,
+    dropped: u64,
+}
+
+impl<T: Serialize + Clone + Send + 'static> EventQueue<T> {
+    pub fn new(event: &'static str, app_handle: AppHandle) -> Self {
+        // The thread exits once the queue, and with it the sender, is dropped.
+        let (sender, receiver) = bounded::<T>(QUEUE_DEPTH);
+        thread::spawn(move || {
+            for payload in receiver {
+                let _ = app_handle.emit(event, payload);
+            }
+        });
+        EventQueue { sender, dropped: 0 }
+    }
+
+    /// Payloads dropped so far because the frontend fell behind.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn send(&mut self, payload: T) {
+        if let Err(TrySendError::Full(_)) = self.sender.try_send(payload) {
+            self.dropped += 1;
+        }
+    }
+}
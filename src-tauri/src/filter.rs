@@ -0,0 +1,233 @@
+use serde::{Deserialize, Serialize};
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+/// Signal types with a preset band-pass.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignalType {
+    Ecg,
+    Emg,
+    Eeg,
+    Eog,
+}
+
+impl SignalType {
+    pub fn band(self) -> (f64, f64) {
+        match self {
+            SignalType::Ecg => (0.5, 40.0),
+            SignalType::Emg => (20.0, 450.0),
+            SignalType::Eeg => (1.0, 45.0),
+            SignalType::Eog => (0.1, 10.0),
+        }
+    }
+}
+
+/// Filter settings of one session. Every stage is optional.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FilterConfig {
+    /// Mains frequency to notch out, usually 50 or 60 Hz.
+    pub notch: Option<f64>,
+    /// High-pass cutoff for removing DC drift.
+    pub highpass: Option<f64>,
+    /// Selects the preset band-pass for this signal type.
+    pub signal: Option<SignalType>,
+    /// Custom band-pass edges, overriding the `signal` preset.
+    pub bandpass: Option<(f64, f64)>,
+}
+
+impl FilterConfig {
+    pub fn band(&self) -> Option<(f64, f64)> {
+        self.bandpass.or_else(|| self.signal.map(SignalType::band))
+    }
+}
+
+/// Normalized biquad coefficients (RBJ audio EQ cookbook).
+#[derive(Clone, Copy, Debug)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Biquad {
+    fn from_raw(b: [f64; 3], a: [f64; 3]) -> Self {
+        Biquad {
+            b0: b[0] / a[0],
+            b1: b[1] / a[0],
+            b2: b[2] / a[0],
+            a1: a[1] / a[0],
+            a2: a[2] / a[0],
+        }
+    }
+
+    fn omega(freq: f64, sample_rate: f64, q: f64) -> (f64, f64) {
+        let w0 = 2.0 * PI * freq / sample_rate;
+        (w0.cos(), w0.sin() / (2.0 * q))
+    }
+
+    pub fn lowpass(freq: f64, sample_rate: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::omega(freq, sample_rate, q);
+        Self::from_raw(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    pub fn highpass(freq: f64, sample_rate: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::omega(freq, sample_rate, q);
+        Self::from_raw(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    pub fn notch(freq: f64, sample_rate: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::omega(freq, sample_rate, q);
+        Self::from_raw(
+            [1.0, -2.0 * cos, 1.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// Direct form II transposed, `z` being the per-channel delay line.
+    #[inline]
+    pub fn run(&self, x: f64, z: &mut [f64; 2]) -> f64 {
+        let y = self.b0 * x + z[0];
+        z[0] = self.b1 * x - self.a1 * y + z[1];
+        z[1] = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// A cascade of biquads with separate state for every channel.
+pub struct FilterChain {
+    sections: Vec<Biquad>,
+    state: Vec<Vec<[f64; 2]>>,
+}
+
+impl FilterChain {
+    const NOTCH_Q: f64 = 30.0;
+
+    pub fn new(config: &FilterConfig, sample_rate: f64, channels: usize) -> Self {
+        // Anything at or above this can't be represented, so the stage is skipped.
+        let nyquist = sample_rate / 2.0;
+        let valid = |f: f64| f > 0.0 && f < nyquist;
+        let mut sections = Vec::new();
+
+        if let Some(freq) = config.notch.filter(|f| valid(*f)) {
+            sections.push(Biquad::notch(freq, sample_rate, Self::NOTCH_Q));
+        }
+        if let Some(freq) = config.highpass.filter(|f| valid(*f)) {
+            sections.push(Biquad::highpass(freq, sample_rate, FRAC_1_SQRT_2));
+        }
+        if let Some((low, high)) = config.band() {
+            if valid(low) {
+                sections.push(Biquad::highpass(low, sample_rate, FRAC_1_SQRT_2));
+            }
+            if valid(high) {
+                sections.push(Biquad::lowpass(high, sample_rate, FRAC_1_SQRT_2));
+            }
+        }
+
+        FilterChain {
+            state: vec![vec![[0.0; 2]; sections.len()]; channels],
+            sections,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+
+    /// Filters one multi-channel sample in place.
+    pub fn process(&mut self, sample: &mut [f32]) {
+        for (value, state) in sample.iter_mut().zip(self.state.iter_mut()) {
+            let mut x = *value as f64;
+            for (section, z) in self.sections.iter().zip(state.iter_mut()) {
+                x = section.run(x, z);
+            }
+            *value = x as f32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f64 = 500.0;
+
+    /// Peak output of `chain` for a unit sine at `freq`, once it has settled.
+    fn gain(chain: &mut FilterChain, freq: f64) -> f64 {
+        let mut peak = 0.0f64;
+        for n in 0..(RATE as usize * 10) {
+            let mut sample = [(2.0 * PI * freq * n as f64 / RATE).sin() as f32];
+            chain.process(&mut sample);
+            if n >= RATE as usize * 8 {
+                peak = peak.max(sample[0].abs() as f64);
+            }
+        }
+        peak
+    }
+
+    fn chain(config: FilterConfig) -> FilterChain {
+        FilterChain::new(&config, RATE, 1)
+    }
+
+    #[test]
+    fn lowpass_passes_low_and_stops_high() {
+        let config = FilterConfig {
+            bandpass: Some((0.0, 40.0)),
+            ..Default::default()
+        };
+        assert!((gain(&mut chain(config.clone()), 5.0) - 1.0).abs() < 0.05);
+        // Half power at the cutoff.
+        assert!((gain(&mut chain(config.clone()), 40.0) - FRAC_1_SQRT_2).abs() < 0.05);
+        assert!(gain(&mut chain(config), 200.0) < 0.05);
+    }
+
+    #[test]
+    fn highpass_removes_drift() {
+        let config = FilterConfig {
+            highpass: Some(1.0),
+            ..Default::default()
+        };
+        assert!((gain(&mut chain(config.clone()), 20.0) - 1.0).abs() < 0.05);
+        assert!(gain(&mut chain(config), 0.1) < 0.05);
+    }
+
+    #[test]
+    fn notch_removes_mains_only() {
+        let config = FilterConfig {
+            notch: Some(50.0),
+            ..Default::default()
+        };
+        assert!(gain(&mut chain(config.clone()), 50.0) < 0.05);
+        assert!((gain(&mut chain(config.clone()), 30.0) - 1.0).abs() < 0.05);
+        assert!((gain(&mut chain(config), 70.0) - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn preset_band_passes_its_band() {
+        let config = FilterConfig {
+            signal: Some(SignalType::Ecg),
+            ..Default::default()
+        };
+        assert!((gain(&mut chain(config.clone()), 10.0) - 1.0).abs() < 0.1);
+        assert!(gain(&mut chain(config.clone()), 0.05) < 0.1);
+        assert!(gain(&mut chain(config), 200.0) < 0.05);
+    }
+
+    #[test]
+    fn skips_cutoffs_at_or_above_nyquist() {
+        let config = FilterConfig {
+            notch: Some(RATE / 2.0),
+            highpass: Some(RATE),
+            ..Default::default()
+        };
+        assert!(chain(config).is_empty());
+    }
+}
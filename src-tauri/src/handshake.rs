@@ -0,0 +1,493 @@
+use crate::board::{self, BoardProfile};
+use crate::capture::Tap;
+use crate::decoder::{Decoder, Protocol};
+use crate::stats::StreamStats;
+use serde::Serialize;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Runtime, Wry};
+
+// How long the line has to stay silent before a board that resets when the
+// port opens counts as booted, and the longest such a reset may take.
+const QUIET: Duration = Duration::from_millis(300);
+const RESET_TIMEOUT: Duration = Duration::from_secs(4);
+// Reply time allowed for each of `INFO` and `WHORU`, per attempt.
+const IDENTIFY_TIMEOUT: Duration = Duration::from_secs(1);
+const IDENTIFY_ATTEMPTS: u32 = 5;
+// Time allowed for the first valid frame after `START`, per attempt.
+const START_TIMEOUT: Duration = Duration::from_secs(2);
+const START_ATTEMPTS: u32 = 3;
+
+/// Steps of bringing up a serial board, reported as the `handshake` event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HandshakeState {
+    ResetWait,
+    Identify,
+    Configure,
+    Start,
+    Streaming,
+    Failed,
+}
+
+/// Payload of the `handshake` event.
+#[derive(Clone, Serialize)]
+struct HandshakeEvent<'a> {
+    port: &'a str,
+    state: HandshakeState,
+    /// 1 for the first try of a state, higher for retries.
+    attempt: u32,
+}
+
+/// Runs the handshake on an open port. The port should have a short read
+/// timeout, around 100 ms, so the timeouts here are kept.
+pub struct Handshake<'a, R: Runtime = Wry> {
+    port_name: &'a str,
+    app_handle: &'a AppHandle<R>,
+    cancelled: Option<Box<dyn Fn() -> bool + 'a>>,
+    identify_attempts: u32,
+}
+
+impl<'a, R: Runtime> Handshake<'a, R> {
+    pub fn new(port_name: &'a str, app_handle: &'a AppHandle<R>) -> Self {
+        Handshake {
+            port_name,
+            app_handle,
+            cancelled: None,
+            identify_attempts: IDENTIFY_ATTEMPTS,
+        }
+    }
+
+    /// Gives up identifying after `attempts`, for trying the board at several
+    /// baud rates.
+    pub fn identify_attempts(mut self, attempts: u32) -> Self {
+        self.identify_attempts = attempts.max(1);
+        self
+    }
+
+    /// Makes every step give up as soon as `cancelled` returns true.
+    pub fn cancellable(mut self, cancelled: impl Fn() -> bool + 'a) -> Self {
+        self.cancelled = Some(Box::new(cancelled));
+        self
+    }
+
+    fn check_cancelled(&self) -> Result<(), String> {
+        match &self.cancelled {
+            Some(cancelled) if cancelled() => {
+                Err(format!("Handshake on {} cancelled", self.port_name))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn report(&self, state: HandshakeState, attempt: u32) {
+        println!(
+            "Handshake on {}: {:?} (attempt {})",
+            self.port_name, state, attempt
+        );
+        let _ = self.app_handle.emit(
+            "handshake",
+            HandshakeEvent {
+                port: self.port_name,
+                state,
+                attempt,
+            },
+        );
+    }
+
+    fn fail(&self, attempt: u32, message: String) -> String {
+        self.report(HandshakeState::Failed, attempt);
+        message
+    }
+
+    /// Brings up a board detected earlier as `expected` and returns a decoder
+    /// that has seen its first valid frame. The samples of that frame go to
+    /// `on_sample`.
+    pub fn connect<P: Read + Write + ?Sized>(
+        &self,
+        port: &mut P,
+        expected: &BoardProfile,
+        tap: &mut Tap,
+        stats: &StreamStats,
+        on_sample: &mut impl FnMut(&[i32]),
+    ) -> Result<Decoder, String> {
+        self.reset_wait(port)?;
+        let board = self.identify(port)?;
+        if board.protocol() != expected.protocol() {
+            return Err(self.fail(
+                1,
+                format!(
+                    "{} on {} no longer matches the detected {}",
+                    board.name, self.port_name, expected.name
+                ),
+            ));
+        }
+        self.bring_up(port, expected, tap, stats, on_sample)
+    }
+
+    /// Brings up a board the user chose by hand, without asking it what it
+    /// is, for firmware that doesn't answer `INFO` or `WHORU`.
+    pub fn connect_as<P: Read + Write + ?Sized>(
+        &self,
+        port: &mut P,
+        board: &BoardProfile,
+        tap: &mut Tap,
+        stats: &StreamStats,
+        on_sample: &mut impl FnMut(&[i32]),
+    ) -> Result<Decoder, String> {
+        self.reset_wait(port)?;
+        self.bring_up(port, board, tap, stats, on_sample)
+    }
+
+    fn bring_up<P: Read + Write + ?Sized>(
+        &self,
+        port: &mut P,
+        board: &BoardProfile,
+        tap: &mut Tap,
+        stats: &StreamStats,
+        on_sample: &mut impl FnMut(&[i32]),
+    ) -> Result<Decoder, String> {
+        let mut decoder = self.configure(port, &board.protocol())?;
+        self.start(port, &mut decoder, tap, stats, on_sample)?;
+        Ok(decoder)
+    }
+
+    /// Discards whatever a board prints while it boots, instead of sleeping
+    /// for as long as the slowest board might take.
+    pub fn reset_wait<P: Read + Write + ?Sized>(&self, port: &mut P) -> Result<(), String> {
+        self.report(HandshakeState::ResetWait, 1);
+        let mut buffer = [0u8; 256];
+        let start_time = Instant::now();
+        let mut last_data = Instant::now();
+        while start_time.elapsed() < RESET_TIMEOUT && last_data.elapsed() < QUIET {
+            self.check_cancelled()?;
+            match port.read(&mut buffer) {
+                Ok(size) if size > 0 => last_data = Instant::now(),
+                Ok(_) => {}
+                Err(e) if is_timeout(&e) => {}
+                Err(e) => return Err(self.fail(1, e.to_string())),
+            }
+        }
+        Ok(())
+    }
+
+    /// Asks the board what it is. Newer firmware describes its frames in
+    /// reply to `INFO`; older firmware only names itself in reply to `WHORU`
+    /// and is looked up in the board table. A board still booting is asked
+    /// again.
+    pub fn identify<P: Read + Write + ?Sized>(&self, port: &mut P) -> Result<BoardProfile, String> {
+        let mut response = String::new();
+        for attempt in 1..=self.identify_attempts {
+            self.report(HandshakeState::Identify, attempt);
+
+            response = self
+                .query(port, b"INFO\n", IDENTIFY_TIMEOUT, |r| {
+                    board::parse_info(r).is_some()
+                })
+                .map_err(|e| self.fail(attempt, e))?;
+            match board::parse_info(&response) {
+                Some(Ok(board)) => return Ok(board),
+                Some(Err(e)) => println!("{} on port {}, trying WHORU", e, self.port_name),
+                None => {}
+            }
+
+            response = self
+                .query(port, b"WHORU\n", IDENTIFY_TIMEOUT, |r| {
+                    board::identify(r).is_some()
+                })
+                .map_err(|e| self.fail(attempt, e))?;
+            if let Some(board) = board::identify(&response) {
+                return Ok(board);
+            }
+        }
+        Err(self.fail(
+            self.identify_attempts,
+            format!("Final response from port {}: {}", self.port_name, response),
+        ))
+    }
+
+    /// Drops replies still in flight, so decoding starts on a clean line.
+    pub fn configure<P: Read + Write + ?Sized>(
+        &self,
+        port: &mut P,
+        protocol: &Protocol,
+    ) -> Result<Decoder, String> {
+        self.report(HandshakeState::Configure, 1);
+        let mut buffer = [0u8; 256];
+        let start_time = Instant::now();
+        while start_time.elapsed() < QUIET {
+            match port.read(&mut buffer) {
+                Ok(size) if size > 0 => {}
+                Ok(_) => break,
+                Err(e) if is_timeout(&e) => break,
+                Err(e) => return Err(self.fail(1, e.to_string())),
+            }
+        }
+        Ok(protocol.decoder())
+    }
+
+    /// Sends `START` until a valid frame comes back.
+    pub fn start<P: Read + Write + ?Sized>(
+        &self,
+        port: &mut P,
+        decoder: &mut Decoder,
+        tap: &mut Tap,
+        stats: &StreamStats,
+        on_sample: &mut impl FnMut(&[i32]),
+    ) -> Result<(), String> {
+        let mut buffer = [0u8; 1024];
+        for attempt in 1..=START_ATTEMPTS {
+            self.report(HandshakeState::Start, attempt);
+            port.write_all(b"START\r\n")
+                .and_then(|_| port.flush())
+                .map_err(|e| self.fail(attempt, e.to_string()))?;
+
+            let start_time = Instant::now();
+            while start_time.elapsed() < START_TIMEOUT {
+                self.check_cancelled()?;
+                match port.read(&mut buffer) {
+                    Ok(size) => {
+                        tap.record(&buffer[..size]);
+                        let mut started = false;
+                        decoder.feed(&buffer[..size], stats, |sample| {
+                            started = true;
+                            on_sample(sample);
+                        });
+                        if started {
+                            self.report(HandshakeState::Streaming, attempt);
+                            return Ok(());
+                        }
+                    }
+                    Err(e) if is_timeout(&e) => {}
+                    Err(e) => return Err(self.fail(attempt, e.to_string())),
+                }
+            }
+        }
+        Err(self.fail(
+            START_ATTEMPTS,
+            format!("No data from {} after START", self.port_name),
+        ))
+    }
+
+    // Sends `command` and collects the reply until `done` accepts it or `timeout`
+    // passes.
+    fn query<P: Read + Write + ?Sized>(
+        &self,
+        port: &mut P,
+        command: &[u8],
+        timeout: Duration,
+        done: impl Fn(&str) -> bool,
+    ) -> Result<String, String> {
+        port.write_all(command).map_err(|e| e.to_string())?;
+        port.flush().map_err(|e| e.to_string())?;
+
+        let mut buffer = [0u8; 1024];
+        let mut response = String::new();
+        let start_time = Instant::now();
+        while start_time.elapsed() < timeout && !done(&response) {
+            self.check_cancelled()?;
+            match port.read(&mut buffer) {
+                Ok(size) => response.push_str(&String::from_utf8_lossy(&buffer[..size])),
+                Err(e) if is_timeout(&e) => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        Ok(response)
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::CaptureHeader;
+    use crate::outlet::StreamSpec;
+    use crate::scaling::Scaling;
+    use crate::session;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use tauri::test::{mock_app, MockRuntime};
+    use tauri::Listener;
+
+    const INFO: &str = r#"{"version":1,"board":"NANO-CLONE","channels":8,"sampleRate":500,"resolution":10,"vref":5.0,"frame":{"sampleWidth":2,"packetSize":20},"checksum":"none"}"#;
+
+    /// A board on the other end of a serial port, answering each command line
+    /// with a canned reply. Reads time out like a real port once it's quiet.
+    struct FakeBoard {
+        info: Option<String>,
+        whoru: Option<String>,
+        line: Vec<u8>,
+        pending: VecDeque<u8>,
+    }
+
+    impl FakeBoard {
+        fn new(info: Option<&str>, whoru: Option<&str>) -> Self {
+            FakeBoard {
+                info: info.map(|reply| format!("{}\r\n", reply)),
+                whoru: whoru.map(|reply| format!("{}\r\n", reply)),
+                line: Vec::new(),
+                // Whatever the firmware prints while it boots.
+                pending: b"Chords firmware booting\r\n".iter().copied().collect(),
+            }
+        }
+
+        fn packet(counter: u8, channels: usize) -> Vec<u8> {
+            let mut packet = vec![0xC7, 0x7C, counter];
+            for channel in 0..channels {
+                packet.extend_from_slice(&(channel as u16 * 100).to_be_bytes());
+            }
+            packet.push(0x01);
+            packet
+        }
+    }
+
+    impl Read for FakeBoard {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pending.is_empty() {
+                thread::sleep(Duration::from_millis(5));
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            // A few bytes at a time, so replies arrive in pieces.
+            let size = buf.len().min(self.pending.len()).min(7);
+            for (out, byte) in buf.iter_mut().zip(self.pending.drain(..size)) {
+                *out = byte;
+            }
+            Ok(size)
+        }
+    }
+
+    impl Write for FakeBoard {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            for &byte in buf {
+                if byte != b'\n' {
+                    self.line.push(byte);
+                    continue;
+                }
+                let command = String::from_utf8_lossy(&self.line).trim().to_string();
+                self.line.clear();
+                let reply = match command.as_str() {
+                    "INFO" => self.info.clone().map(String::into_bytes),
+                    "WHORU" => self.whoru.clone().map(String::into_bytes),
+                    "START" => {
+                        let channels = board::identify(self.whoru.as_deref().unwrap_or(""))
+                            .map_or(8, |board| board.channels);
+                        Some([Self::packet(0, channels), Self::packet(1, channels)].concat())
+                    }
+                    _ => None,
+                };
+                self.pending.extend(reply.unwrap_or_default());
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn tap(board: &BoardProfile) -> Tap {
+        Tap::with_header(
+            session::get_or_create("handshake-test"),
+            CaptureHeader {
+                version: 1,
+                session: "handshake-test".to_string(),
+                protocol: board.protocol(),
+                spec: StreamSpec {
+                    name: board.name.clone(),
+                    stream_type: "EXG".to_string(),
+                    channels: board.channels,
+                    sample_rate: 500.0,
+                    source_id: "handshake-test".to_string(),
+                },
+                scaling: Scaling::new(board.resolution, board.vref),
+                started: String::new(),
+            },
+        )
+    }
+
+    /// Runs `connect` against `port`, returning the outcome, the reported
+    /// states and the samples of the first frame.
+    fn connect(
+        port: &mut FakeBoard,
+        expected: &str,
+    ) -> (Result<Decoder, String>, Vec<String>, Vec<Vec<i32>>) {
+        let app = mock_app();
+        let app_handle: &AppHandle<MockRuntime> = app.handle();
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let seen = states.clone();
+        app_handle.listen_any("handshake", move |event| {
+            let payload: serde_json::Value = serde_json::from_str(event.payload()).unwrap();
+            seen.lock()
+                .unwrap()
+                .push(payload["state"].as_str().unwrap().to_string());
+        });
+
+        let expected = board::named(expected).unwrap();
+        let stats = StreamStats::default();
+        let mut samples = Vec::new();
+        let result = Handshake::new("fake", app_handle).connect(
+            port,
+            &expected,
+            &mut tap(&expected),
+            &stats,
+            &mut |sample: &[i32]| samples.push(sample.to_vec()),
+        );
+        let states = states.lock().unwrap().clone();
+        (result, states, samples)
+    }
+
+    #[test]
+    fn streams_a_board_that_answers_info() {
+        let mut port = FakeBoard::new(Some(INFO), None);
+        let (result, states, samples) = connect(&mut port, "NANO-CLONE");
+        assert!(result.is_ok());
+        assert_eq!(
+            states,
+            ["reset-wait", "identify", "configure", "start", "streaming"]
+        );
+        assert_eq!(samples[0], [0, 100, 200, 300, 400, 500, 600, 700]);
+    }
+
+    #[test]
+    fn falls_back_to_whoru_after_a_bad_descriptor() {
+        let mut port = FakeBoard::new(Some("{not json"), Some("UNO-R4"));
+        let (result, states, samples) = connect(&mut port, "UNO-R4");
+        assert!(result.is_ok());
+        assert_eq!(
+            states,
+            ["reset-wait", "identify", "configure", "start", "streaming"]
+        );
+        assert_eq!(samples[0], [0, 100, 200, 300, 400, 500]);
+    }
+
+    #[test]
+    fn falls_back_to_whoru_when_info_goes_unanswered() {
+        let mut port = FakeBoard::new(None, Some("UNO-R4"));
+        let (result, states, _) = connect(&mut port, "UNO-R4");
+        assert!(result.is_ok());
+        assert_eq!(states[1..3], ["identify", "configure"]);
+    }
+
+    #[test]
+    fn fails_on_a_different_board() {
+        let mut port = FakeBoard::new(None, Some("UNO-R4"));
+        let (result, states, _) = connect(&mut port, "NANO-CLONE");
+        assert!(result.is_err());
+        assert_eq!(states, ["reset-wait", "identify", "failed"]);
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let app = mock_app();
+        let mut port = FakeBoard::new(Some(INFO), None);
+        let handshake = Handshake::new("fake", app.handle()).cancellable(|| true);
+        assert!(handshake.reset_wait(&mut port).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use crate::detection::SerialPortEntry;
+use lazy_static::lazy_static;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+// How often the monitor looks for new hotplug events.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+lazy_static! {
+    // Counts serial devices plugged in since launch, so `wait` can tell when
+    // another one arrives.
+    static ref ADDED: (Mutex<u64>, Condvar) = (Mutex::new(0), Condvar::new());
+}
+
+/// Starts watching for serial devices coming and going, reported as the
+/// `device_added` and `device_removed` events with a `SerialPortEntry`.
+pub fn start(app_handle: AppHandle) {
+    thread::spawn(move || {
+        if let Err(e) = watch(&app_handle) {
+            println!("Hotplug monitor stopped: {}", e);
+        }
+    });
+}
+
+/// Blocks until a serial device is plugged in or `timeout` passes.
+pub fn wait(timeout: Duration) {
+    let (count, added) = &*ADDED;
+    let count = count.lock().unwrap();
+    let seen = *count;
+    let _ = added.wait_timeout_while(count, timeout, |now| *now == seen);
+}
+
+fn notify(app_handle: &AppHandle, added: bool, port: SerialPortEntry) {
+    if added {
+        println!("Serial device added: {}", port.port);
+        let (count, added) = &*ADDED;
+        *count.lock().unwrap() += 1;
+        added.notify_all();
+        let _ = app_handle.emit("device_added", port);
+    } else {
+        println!("Serial device removed: {}", port.port);
+        let _ = app_handle.emit("device_removed", port);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn watch(app_handle: &AppHandle) -> Result<(), String> {
+    use udev::{EventType, MonitorBuilder};
+
+    let socket = MonitorBuilder::new()
+        .and_then(|builder| builder.match_subsystem("tty"))
+        .and_then(|builder| builder.listen())
+        .map_err(|e| e.to_string())?;
+    loop {
+        for event in socket.iter() {
+            let added = match event.event_type() {
+                EventType::Add => true,
+                EventType::Remove => false,
+                _ => continue,
+            };
+            let property = |name: &str| {
+                event
+                    .property_value(name)
+                    .map(|value| value.to_string_lossy().into_owned())
+            };
+            // Only USB bridges come and go; this skips virtual terminals.
+            let vid = match property("ID_VENDOR_ID") {
+                Some(vid) => u16::from_str_radix(&vid, 16).ok(),
+                None => continue,
+            };
+            let port = match event.devnode() {
+                Some(node) => node.to_string_lossy().into_owned(),
+                None => continue,
+            };
+            let entry = SerialPortEntry {
+                port,
+                vid,
+                pid: property("ID_MODEL_ID").and_then(|pid| u16::from_str_radix(&pid, 16).ok()),
+                serial_number: property("ID_SERIAL_SHORT"),
+                manufacturer: property("ID_VENDOR"),
+                product: property("ID_MODEL"),
+            };
+            notify(app_handle, added, entry);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// Without udev, compares the port list between polls.
+#[cfg(not(target_os = "linux"))]
+fn watch(app_handle: &AppHandle) -> Result<(), String> {
+    let mut known = crate::detection::list_ports()?;
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let ports = match crate::detection::list_ports() {
+            Ok(ports) => ports,
+            Err(e) => {
+                println!("Failed to list serial ports: {}", e);
+                continue;
+            }
+        };
+        for port in &ports {
+            if !known.iter().any(|old| old.port == port.port) {
+                notify(app_handle, true, port.clone());
+            }
+        }
+        for old in known {
+            if !ports.iter().any(|port| port.port == old.port) {
+                notify(app_handle, false, old);
+            }
+        }
+        known = ports;
+    }
+}
@@ -0,0 +1,78 @@
+use crate::board::BoardProfile;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use tauri::{AppHandle, Manager};
+
+// Kept in the app's config directory.
+const FILE_NAME: &str = "last_device.json";
+
+/// The device the connector last streamed from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "transport", rename_all = "lowercase")]
+pub enum LastDevice {
+    #[serde(rename_all = "camelCase")]
+    Serial {
+        port: String,
+        /// Finds the board again if it comes back under another port name.
+        usb_serial: Option<String>,
+        board: BoardProfile,
+        baud_rate: u32,
+    },
+    Ble {
+        id: String,
+    },
+    Wifi {
+        url: String,
+    },
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Remembered {
+    pub device: Option<LastDevice>,
+    /// Start streaming from `device` as soon as the app opens, for lab
+    /// stations that should come up without anyone clicking.
+    #[serde(default)]
+    pub auto_start: bool,
+}
+
+fn path(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    app_handle
+        .path()
+        .app_config_dir()
+        .map(|dir| dir.join(FILE_NAME))
+        .map_err(|e| e.to_string())
+}
+
+/// What was saved, or nothing if it can't be read.
+pub fn load(app_handle: &AppHandle) -> Remembered {
+    path(app_handle)
+        .and_then(|path| fs::read_to_string(path).map_err(|e| e.to_string()))
+        .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+        .unwrap_or_default()
+}
+
+fn save(app_handle: &AppHandle, remembered: &Remembered) -> Result<(), String> {
+    let path = path(app_handle)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let json = serde_json::to_string_pretty(remembered).map_err(|e| e.to_string())?;
+    fs::write(path, json).map_err(|e| e.to_string())
+}
+
+/// Records `device` as the one to try first on the next launch.
+pub fn remember(app_handle: &AppHandle, device: LastDevice) {
+    let mut remembered = load(app_handle);
+    remembered.device = Some(device);
+    if let Err(e) = save(app_handle, &remembered) {
+        println!("Failed to remember the last device: {}", e);
+    }
+}
+
+pub fn set_auto_start(app_handle: &AppHandle, enabled: bool) -> Result<(), String> {
+    let mut remembered = load(app_handle);
+    remembered.auto_start = enabled;
+    save(app_handle, &remembered)
+}
@@ -1,3 +1,7 @@
+// Shared with the benchmarks.
+pub mod decoder;
+pub mod stats;
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
   tauri::Builder::default()
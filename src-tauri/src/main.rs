@@ -3,279 +3,338 @@ use btleplug::platform::Peripheral;
 use futures::future::ok;
 use futures::StreamExt; // Changed from futures_util to futures
 use lazy_static::lazy_static;
-use lsl::Pushable; // Add the necessary imports
-use lsl::StreamOutlet;
-use lsl::{ChannelFormat, StreamInfo};
 use serde_json::json; // Add this import at the top
 use serialport;
+use std::collections::HashMap;
 use std::io::{self, Read, Write};
-use std::sync::atomic::{AtomicU8, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 use tauri::{self, AppHandle, Emitter}; // Import Emitter along with AppHandle
-use tokio::sync::mpsc;
 use tungstenite::connect;
 use tungstenite::protocol::Message;
 use url::Url;
-lazy_static! {
-    static ref BAUDRATE: Arc<Mutex<u32>> = Arc::new(Mutex::new(230400)); // Default baud rate
-    static ref PACKET_SIZE: Arc<Mutex<usize>> = Arc::new(Mutex::new(16)); // Default baud rate
-    static ref CHANNELS: Arc<Mutex<usize>> = Arc::new(Mutex::new(6)); // Default baud rate
-    static ref BITS: Arc<Mutex<String>> = Arc::new(Mutex::new("10".into())); // Default baud rate
 
-    static ref SAMPLE_RATE: Arc<Mutex<f64>> = Arc::new(Mutex::new(500.0)); // Default baud rate
+mod board;
+mod broadcast;
+mod capture;
+mod detection;
+mod ecg;
+mod eeg;
+mod emg;
+mod events;
+mod filter;
+mod handshake;
+mod hotplug;
+mod last_device;
+mod mqtt;
+mod osc;
+mod outlet;
+mod pipeline;
+mod playback;
+mod preview;
+mod quality;
+mod recording;
+mod replay;
+mod ring;
+mod scaling;
+mod session;
+mod spectrum;
+mod spectrum_feed;
+// Built once, in the library the benchmarks link as well.
+use app_lib::{decoder, stats};
+use board::BoardProfile;
+use capture::Tap;
+use decoder::Protocol;
+use detection::{DetectedBoard, SerialPortEntry, HANDSHAKE_READ_TIMEOUT};
+use ecg::EcgConfig;
+use eeg::{BandPower, BandPowerConfig};
+use emg::EmgConfig;
+use filter::FilterConfig;
+use handshake::Handshake;
+use last_device::{LastDevice, Remembered};
+use mqtt::MqttConfig;
+use osc::OscConfig;
+use outlet::StreamSpec;
+use pipeline::Pipeline;
+use playback::{PlaybackInfo, PlaybackOptions};
+use preview::PreviewConfig;
+use quality::QualityConfig;
+use ring::{Consumer, Pop, Producer};
+use scaling::{OutputConfig, OutputMode, RawFormat, Scaling};
+use session::{BLE_SESSION, SERIAL_SESSION, WIFI_SESSION};
+use spectrum_feed::SpectrumConfig;
+use stats::StreamStats;
+
+lazy_static! {
+    static ref BOARD: Arc<Mutex<BoardProfile>> = Arc::new(Mutex::new(BoardProfile::default())); // Last detected board
+    static ref DETECTED: Mutex<HashMap<String, DetectedBoard>> = Mutex::new(HashMap::new()); // Boards found by the last detection, with their baud rates
 }
-use std::collections::VecDeque;
 use tauri::Manager;
 
 #[tauri::command]
-async fn detect_arduino() -> Result<String, String> {
-    tokio::task::spawn_blocking(|| detect_arduino_internal())
+async fn detect_arduino(
+    baud_rates: Option<Vec<u32>>,
+    app_handle: AppHandle,
+) -> Result<Vec<DetectedBoard>, String> {
+    let baud_rates = baud_rates.unwrap_or_else(|| detection::DEFAULT_BAUD_RATES.to_vec());
+    let boards = tokio::task::spawn_blocking(move || detection::detect(&app_handle, &baud_rates))
         .await
-        .map_err(|e| format!("Task panicked: {:?}", e))?
+        .map_err(|e| format!("Task panicked: {:?}", e))??;
+    let mut detected = DETECTED.lock().unwrap();
+    detected.clear();
+    for found in &boards {
+        detected.insert(found.port.clone(), found.clone());
+    }
+    use_detected(&boards[0]);
+    Ok(boards)
 }
 
-// Your original function renamed to detect_arduino_internal
-fn detect_arduino_internal() -> Result<String, String> {
-    loop {
-        let ports = serialport::available_ports().expect("No ports found!");
-
-        for port_info in ports {
-            let port_name = port_info.port_name;
-            println!("Attempting to connect to port: {}", port_name);
+#[tauri::command]
+fn cancel_detection() {
+    detection::cancel();
+}
 
-            if port_name.contains("BLTH")
-                || port_name.contains("Bluetooth")
-                || port_name.contains("console")
-            {
-                continue;
-            }
-            if let serialport::SerialPortType::UsbPort(info) = port_info.port_type {
-                // Check if the VID matches your Arduino device
-                if info.vid == 6790 || matches!(info.pid, 67 | 579 | 29987 | 66 | 24577) {
-                    *BAUDRATE.lock().unwrap() = 115200; // Change the baud rate dynamically
-                    *SAMPLE_RATE.lock().unwrap() = 250.0;
-                }
-            }
+// Makes `found` the board `start_streaming` connects to.
+fn use_detected(found: &DetectedBoard) {
+    *BOARD.lock().unwrap() = found.board.clone();
+}
 
-            match serialport::new(&port_name, *BAUDRATE.lock().unwrap())
-                .timeout(Duration::from_secs(3))
-                .open()
-            {
-                Ok(mut port) => {
-                    thread::sleep(Duration::from_secs(3)); // Allow Arduino to reset
-                    let command = b"WHORU\n";
+// The stream `start_streaming` publishes for `board`.
+fn serial_stream(board: &BoardProfile) -> (StreamSpec, Scaling) {
+    let spec = StreamSpec {
+        name: "UDL".into(),
+        stream_type: "Biopotential_Signals".into(),
+        channels: board.channels,
+        sample_rate: board.sample_rate.unwrap_or(detection::DEFAULT_SAMPLE_RATE),
+        source_id: "Chords".into(),
+    };
+    (spec, board.scaling())
+}
 
-                    if let Err(e) = port.write_all(command) {
-                        println!("Failed to write to port: {}. Error: {:?}", port_name, e);
-                        continue;
-                    }
-                    port.flush().expect("Failed to flush port");
-
-                    let mut buffer: Vec<u8> = vec![0; 1024];
-                    let mut response = String::new();
-                    let start_time = Instant::now();
-                    let timeout = Duration::from_secs(10);
-
-                    while start_time.elapsed() < timeout {
-                        match port.read(&mut buffer) {
-                            Ok(size) => {
-                                if size > 0 {
-                                    response.push_str(&String::from_utf8_lossy(&buffer[..size]));
-                                    if response.contains("UNO-R4")
-                                        || response.contains("UNO-R3")
-                                        || response.contains("GIGA-R1")
-                                        || response.contains("RPI-PICO-RP2040")
-                                        || response.contains("UNO-CLONE")
-                                        || response.contains("NANO-CLONE")
-                                        || response.contains("MEGA-2560-R3")
-                                        || response.contains("MEGA-2560-CLONE")
-                                        || response.contains("GENUINO-UNO")
-                                        || response.contains("NANO-CLASSIC")
-                                        || response.contains("STM32G4-CORE-BOARD")
-                                        || response.contains("STM32F4-BLACK-PILL")
-                                        || response.contains("NPG-LITE")
-                                    {
-                                        if response.contains("UNO-R3")
-                                            || response.contains("UNO-CLONE")
-                                            || response.contains("NANO-CLONE")
-                                            || response.contains("MEGA-2560-R3")
-                                            || response.contains("MEGA-2560-CLONE")
-                                            || response.contains("GENUINO-UNO")
-                                            || response.contains("NANO-CLASSIC")
-                                        {
-                                            *BITS.lock().unwrap() = "10".into();
-                                        } else if response.contains("GIGA-R1") {
-                                            *BITS.lock().unwrap() = "16".into();
-                                        // Change the bits dynamically
-                                        } else if response.contains("RPI-PICO-RP2040")
-                                            || response.contains("STM32G4-CORE-BOARD")
-                                            || response.contains("STM32F4-BLACK-PILL")
-                                            || response.contains("NPG-LITE")
-                                        {
-                                            *BITS.lock().unwrap() = "12".into();
-                                            // Change the bits dynamically
-                                        }  else if response.contains("UNO-R4") {
-                                            *BITS.lock().unwrap() = "14".into();
-                                            // Change the bits dynamically
-                                        }
-                                        if response.contains("NANO-CLONE")
-                                            || response.contains("NANO-CLASSIC")
-                                            || response.contains("STM32F4-BLACK-PILL")
-                                        {
-                                            *PACKET_SIZE.lock().unwrap() = 20; // Change the baud rate dynamically
-                                            *CHANNELS.lock().unwrap() = 8; // Change the baud rate dynamically
-                                        }
-                                        if response.contains("MEGA-2560-R3")
-                                            || response.contains("MEGA-2560-CLONE")
-                                            || response.contains("STM32G4-CORE-BOARD")
-                                        {
-                                            *PACKET_SIZE.lock().unwrap() = 36; // Change the baud rate dynamically
-                                            *CHANNELS.lock().unwrap() = 16; // Change the baud rate dynamically
-                                        }
-                                        if response.contains("RPI-PICO-RP2040")
-                                            || response.contains("NPG-LITE")
-                                        {
-                                            *PACKET_SIZE.lock().unwrap() = 10; // Change the baud rate dynamically
-                                            *CHANNELS.lock().unwrap() = 3; // Change the baud rate dynamically
-                                        }
-                                        println!("Valid device found on port: {}", port_name);
-                                        drop(port);
-                                        return Ok(port_name); // Return the found port name directly
-                                    }
-                                }
-                            }
-                            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
-                            Err(e) => {
-                                println!("Failed to read from port: {}. Error: {:?}", port_name, e);
-                                break;
-                            }
-                        }
-                    }
-                    println!("Final response from port {}: {}", port_name, response);
+// Seconds of samples the serial reader may get ahead of the pipeline before
+// new samples are dropped.
+const RING_SECONDS: f64 = 2.0;
 
-                    drop(port);
-                }
-                Err(e) => {
-                    println!("Failed to open port: {}. Error: {:?}", port_name, e);
-                }
-            }
+#[tauri::command]
+async fn start_streaming(
+    port_name: String,
+    output: Option<OutputConfig>,
+    app_handle: AppHandle,
+) {
+    // The rate detection confirmed for this port, not one left over from
+    // another.
+    let baud_rate = match DETECTED.lock().unwrap().get(&port_name) {
+        Some(found) => {
+            use_detected(found);
+            found.baud_rate
         }
+        None => detection::DEFAULT_BAUD_RATES[0],
+    };
+    let board = BOARD.lock().unwrap().clone();
+    let _ = tokio::task::spawn_blocking(move || {
+        stream_serial(port_name, board, baud_rate, true, output, app_handle)
+    })
+    .await;
+}
 
-        println!("No valid device found, retrying in 5 seconds...");
-        thread::sleep(Duration::from_secs(5)); // Wait before trying again
-    }
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManualBoard {
+    /// A name from `list_board_profiles`, for resolution, reference voltage
+    /// and frame layout.
+    board: String,
+    baud_rate: u32,
+    channels: usize,
+    sample_rate: f64,
 }
 
+// Streams from a board the user picked instead of detecting it.
 #[tauri::command]
-async fn start_streaming(port_name: String, app_handle: AppHandle) {
-    const START_BYTE_1: u8 = 0xC7;
-    const START_BYTE_2: u8 = 0x7C;
-    const END_BYTE: u8 = 0x01;
-
-    // Create a channel for communication
-    let (tx, mut rx) = mpsc::channel::<Vec<i16>>(100);
-
-    // Create StreamInfo as before
-    let mut info = StreamInfo::new(
-        "UDL",
-        "Biopotential_Signals",
-        (*CHANNELS.lock().unwrap()).try_into().unwrap(),
-        *SAMPLE_RATE.lock().unwrap(),
-        lsl::ChannelFormat::Int16,
-        "Chords",
-    )
-    .unwrap();
-   
-    let mut desc = info.desc();
-    let mut resinfo = desc.append_child("resinfo");
-    resinfo.append_child_value("resolution", &*BITS.lock().unwrap());
-
-    // Print the XML description
-    println!("LSL Stream XML Description:");
-    match info.to_xml() {
-        Ok(xml) => println!("{}", xml),
-        Err(e) => println!("Failed to get XML description: {:?}", e),
+async fn start_streaming_manual(
+    port_name: String,
+    manual: ManualBoard,
+    output: Option<OutputConfig>,
+    app_handle: AppHandle,
+) -> Result<(), String> {
+    let mut board = board::named(&manual.board)
+        .ok_or_else(|| format!("Unknown board profile {}", manual.board))?;
+    if manual.baud_rate == 0
+        || manual.channels == 0
+        || !manual.sample_rate.is_finite()
+        || manual.sample_rate <= 0.0
+    {
+        return Err("Baud rate, channels and sample rate must be positive".to_string());
     }
- // Create StreamOutlet in the same thread
-    let (tx, rx) = std::sync::mpsc::channel::<Vec<i16>>();
-    let outlet = Arc::new(Mutex::new(StreamOutlet::new(&info, 0, 360).unwrap()));
+    board.channels = manual.channels;
+    board.sample_rate = Some(manual.sample_rate);
+    *BOARD.lock().unwrap() = board.clone();
+    let _ = tokio::task::spawn_blocking(move || {
+        stream_serial(
+            port_name,
+            board,
+            manual.baud_rate,
+            false,
+            output,
+            app_handle,
+        )
+    })
+    .await;
+    Ok(())
+}
+
+#[tauri::command]
+fn get_last_device(app_handle: AppHandle) -> Remembered {
+    last_device::load(&app_handle)
+}
+
+#[tauri::command]
+fn set_auto_start(enabled: bool, app_handle: AppHandle) -> Result<(), String> {
+    last_device::set_auto_start(&app_handle, enabled)
+}
+
+// Checks that the remembered serial board is still there, so streaming can
+// start without probing every port.
+#[tauri::command]
+async fn find_last_serial_device(app_handle: AppHandle) -> Result<DetectedBoard, String> {
+    let (port, usb_serial, baud_rate) = match last_device::load(&app_handle).device {
+        Some(LastDevice::Serial {
+            port,
+            usb_serial,
+            baud_rate,
+            ..
+        }) => (port, usb_serial, baud_rate),
+        _ => return Err("No serial device remembered".to_string()),
+    };
+    let port = usb_serial
+        .as_deref()
+        .and_then(detection::port_with_serial)
+        .unwrap_or(port);
+    let found =
+        tokio::task::spawn_blocking(move || detection::detect_on(&port, baud_rate, &app_handle))
+            .await
+            .map_err(|e| format!("Task panicked: {:?}", e))??;
+    DETECTED
+        .lock()
+        .unwrap()
+        .insert(found.port.clone(), found.clone());
+    use_detected(&found);
+    Ok(found)
+}
+
+#[tauri::command]
+fn list_serial_ports() -> Result<Vec<SerialPortEntry>, String> {
+    detection::list_ports()
+}
+
+#[tauri::command]
+fn list_board_profiles() -> Vec<BoardProfile> {
+    board::profiles()
+}
+
+// Reads `board` on `port_name` and publishes it until the port goes away.
+// Without `identify` the board is trusted to be what the caller says. Blocks,
+// so it runs on a blocking thread.
+fn stream_serial(
+    port_name: String,
+    board: BoardProfile,
+    baud_rate: u32,
+    identify: bool,
+    output: Option<OutputConfig>,
+    app_handle: AppHandle,
+) {
+    let output = output.unwrap_or_default();
+    let (spec, scaling) = serial_stream(&board);
+    let scaling = scaling.with_config(&output);
+
+    // Create the outlets in the same thread
+    let mut pipeline = match Pipeline::new(
+        SERIAL_SESSION,
+        spec,
+        scaling,
+        output.mode.unwrap_or(OutputMode::Raw),
+        output
+            .raw_format
+            .unwrap_or_else(|| scaling.serial_raw_format()),
+        app_handle.clone(),
+    ) {
+        Ok(pipeline) => pipeline,
+        Err(e) => {
+            println!("Failed to create LSL outlet: {}", e);
+            return;
+        }
+    };
+    let stats = pipeline.stats();
+    let mut tap = Tap::new(&pipeline, board.protocol());
+    let spec = pipeline.spec();
+    let (mut producer, mut consumer) = ring::ring(
+        spec.channels,
+        (spec.sample_rate * RING_SECONDS) as usize,
+    );
+    // Follows the board if it comes back under another name after a USB
+    // re-enumeration.
+    let usb_serial = detection::serial_number(&port_name);
+    let mut port_name = port_name;
     // Use spawn_blocking to handle the task in a separate thread
     tokio::task::spawn_blocking(move || loop {
-        match serialport::new(&port_name, *BAUDRATE.lock().unwrap())
+        if let Some(found) = usb_serial.as_deref().and_then(detection::port_with_serial) {
+            if found != port_name {
+                println!("Device moved from {} to {}", port_name, found);
+                port_name = found;
+            }
+        }
+        match serialport::new(&port_name, baud_rate)
             .timeout(Duration::from_secs(3))
             .open()
         {
             Ok(mut port) => {
-                let start_command = b"START\r\n";
-
-                for i in 1..=3 {
-                    if let Err(e) = port.write_all(start_command) {}
-                    println!("Connected to device on port: {}", port_name);
-                    thread::sleep(Duration::from_millis(1000));
+                let _ = port.set_timeout(HANDSHAKE_READ_TIMEOUT);
+                let mut queue = |sample: &[i32]| {
+                    if !producer.push(sample) {
+                        stats.add_overflow();
+                    }
+                };
+                let handshake = Handshake::new(&port_name, &app_handle);
+                let connected = if identify {
+                    handshake.connect(&mut *port, &board, &mut tap, &stats, &mut queue)
+                } else {
+                    handshake.connect_as(&mut *port, &board, &mut tap, &stats, &mut queue)
+                };
+                let mut decoder = match connected {
+                    Ok(decoder) => decoder,
+                    Err(e) => {
+                        println!("Handshake with {} failed: {}", port_name, e);
+                        thread::sleep(Duration::from_secs(5));
+                        continue;
+                    }
+                };
+                let _ = port.set_timeout(Duration::from_secs(3));
+                println!("Connected to device on port: {}", port_name);
+                if identify {
+                    last_device::remember(
+                        &app_handle,
+                        LastDevice::Serial {
+                            port: port_name.clone(),
+                            usb_serial: usb_serial.clone(),
+                            board: board.clone(),
+                            baud_rate,
+                        },
+                    );
                 }
 
-                println!("Finished sending commands.");
-
                 let mut buffer: Vec<u8> = vec![0; 1024];
-                let mut accumulated_buffer: Vec<u8> = Vec::new();
-
-                let mut packet_count = 0;
-                let mut sample_count = 0;
-                let mut byte_count = 0;
-                let start_time = Instant::now();
                 let mut last_print_time = Instant::now();
-                packet_count += 1;
 
                 loop {
                     match port.read(&mut buffer) {
                         Ok(size) => {
-                            accumulated_buffer.extend_from_slice(&buffer[..size]);
-                            byte_count += size;
-
-                            while accumulated_buffer.len() >= *PACKET_SIZE.lock().unwrap() {
-                                if accumulated_buffer[0] == START_BYTE_1
-                                    && accumulated_buffer[1] == START_BYTE_2
-                                {
-                                    if accumulated_buffer[*PACKET_SIZE.lock().unwrap() - 1]
-                                        == END_BYTE
-                                    {
-                                        let packet = accumulated_buffer
-                                            .drain(..*PACKET_SIZE.lock().unwrap())
-                                            .collect::<Vec<u8>>();
-                                        sample_count += 1;
-                                        let data: Vec<i16> = (0..*CHANNELS.lock().unwrap())
-                                            .map(|i| {
-                                                let idx = 3 + (i * 2);
-                                                let high = packet[idx] as i16;
-                                                let low = packet[idx + 1] as i16;
-                                                (high << 8) | low
-                                            })
-                                            .collect();
-                                        if tx.send(data).is_err() {
-                                            println!("Failed to send data to the main thread.");
-                                            break;
-                                        }
-                                    } else {
-                                        accumulated_buffer.drain(..1);
-                                    }
-                                } else {
-                                    accumulated_buffer.drain(..1);
-                                }
-                            }
+                            tap.record(&buffer[..size]);
+                            decoder.feed(&buffer[..size], &stats, &mut queue);
 
                             if last_print_time.elapsed() >= Duration::from_secs(1) {
-                                let elapsed = start_time.elapsed().as_secs_f32();
-                                let samples_per_second = format!("{:.2}", sample_count as f32);
-
                                 let _ = app_handle.emit("connection", "Connected ");
-                                if elapsed > 2.0 {
-                                    // Wait at least 2 seconds
-                                    let _ = app_handle.emit("samplerate", samples_per_second);
-                                }
-                                let _ = app_handle.emit("lsl", "uidserial007");
-                                sample_count = 0;
+                                let _ = app_handle.emit("lsl", SERIAL_SESSION);
                                 last_print_time = Instant::now();
                             }
                         }
@@ -290,6 +349,9 @@ async fn start_streaming(port_name: String, app_handle: AppHandle) {
                     }
                 }
             }
+            Err(e) if usb_serial.is_some() => {
+                println!("Waiting for device on {} to come back: {}", port_name, e);
+            }
             Err(e) => {
                 println!("Failed to connect to device on {}: {}", port_name, e);
                 break;
@@ -297,235 +359,159 @@ async fn start_streaming(port_name: String, app_handle: AppHandle) {
         }
 
         println!("Device disconnected, checking for new devices...");
-        thread::sleep(Duration::from_secs(5));
+        hotplug::wait(Duration::from_secs(5));
     });
-    while let Ok(data) = rx.recv() {
-        if let Ok(outlet) = outlet.lock() {
-            outlet.push_sample(&data).unwrap_or_else(|e| {
-                println!("Failed to push data to LSL: {:?}", e);
-            });
+    publish(&mut consumer, &mut pipeline);
+}
+
+// Publishes what a transport's reader queues until the reader drops its end
+// of the ring.
+fn publish(consumer: &mut Consumer, pipeline: &mut Pipeline) {
+    let mut data = Vec::new();
+    loop {
+        match consumer.pop(&mut data) {
+            Pop::Sample => pipeline.push(&data),
+            Pop::Empty => consumer.wait(Duration::from_millis(100)),
+            Pop::Closed => break,
         }
     }
 }
 
-fn calculate_rate(data_size: usize, elapsed_time: f64) -> f64 {
-    data_size as f64 / elapsed_time
-}
+// Wire formats of the NPG-Lite's wireless transports.
+const NPG_WIFI: Protocol = Protocol::Wifi { sample_width: 2 };
+const NPG_BLE: Protocol = Protocol::Ble { sample_width: 2 };
+
+// Where the NPG-Lite serves its samples unless told otherwise.
+const NPG_WIFI_URL: &str = "ws://multi-emg.local:81";
 
 #[tauri::command]
-async fn start_wifistreaming(app_handle: AppHandle) {
+async fn start_wifistreaming(
+    url: Option<String>,
+    output: Option<OutputConfig>,
+    app_handle: AppHandle,
+) {
     tauri::async_runtime::spawn_blocking(move || {
         let stream_name = "NPG-Lite";
-        let mut info = StreamInfo::new(
-            stream_name,
-            "EXG",
-            3,
-            500.0,
-            ChannelFormat::Float32,
-            "uidwifi007",
+        let output = output.unwrap_or_default();
+        let spec = StreamSpec {
+            name: stream_name.into(),
+            stream_type: "EXG".into(),
+            channels: 3,
+            sample_rate: 500.0,
+            source_id: "uidwifi007".into(),
+        };
+        let scaling = Scaling::new(12, 3.3).with_config(&output);
+        let mode = output.mode.unwrap_or(OutputMode::Raw);
+        let raw_format = output.raw_format.unwrap_or(RawFormat::Float32);
+        let mut pipeline = Pipeline::new(
+            WIFI_SESSION,
+            spec,
+            scaling,
+            mode,
+            raw_format,
+            app_handle.clone(),
         )
-        .expect("Failed to create StreamInfo");
-        let mut desc = info.desc();
-        let mut resinfo = desc.append_child("resinfo");
-        resinfo.append_child_value("resolution", "12");
-
-        let outlet = StreamOutlet::new(&info, 0, 360).expect("Failed to create StreamOutlet");
+        .expect("Failed to create StreamOutlet");
 
-        let ws_url = "ws://multi-emg.local:81";
+        let ws_url = url.unwrap_or_else(|| NPG_WIFI_URL.to_string());
         let (mut socket, _) =
-            connect(Url::parse(ws_url).expect("Failed to parse URL")).expect("WebSocket failed");
+            connect(Url::parse(&ws_url).expect("Failed to parse URL")).expect("WebSocket failed");
         println!("{} WebSocket connected!", stream_name);
+        last_device::remember(&app_handle, LastDevice::Wifi { url: ws_url });
         let _ = app_handle.emit("connection", "Connected");
-        let mut block_size = 13;
-        let mut packet_size = 0;
-        let mut data_size = 0;
-        let mut sample_size = 0;
-        let mut previous_sample_number: Option<u8> = None;
-        let mut previous_data = vec![];
-        let mut sample_count = 0;
-        let mut samplelost = 0;
-        let mut start_time = Instant::now();
-        const BUFFER_SIZE: usize = 20;
-
-        let mut buffer: VecDeque<f64> = VecDeque::with_capacity(BUFFER_SIZE);
-
-        loop {
-            match socket.read_message() {
-                Ok(Message::Binary(data)) => {
-                    data_size += data.len();
-                    let elapsed_time = start_time.elapsed().as_secs_f64();
-
-                    if elapsed_time >= 1.0 {
-                        let samples_per_second = calculate_rate(sample_size, elapsed_time);
-                        let refresh_rate = calculate_rate(packet_size, elapsed_time);
-                        let bytes_per_second = calculate_rate(data_size, elapsed_time);
-
-                        let sps = samples_per_second.floor();
-
-                        // Maintain the circular buffer (add new, remove oldest if over capacity)
-                        if buffer.len() == BUFFER_SIZE {
-                            buffer.pop_front();
-                        }
-                        buffer.push_back(sps);
-
-                        // Calculate average
-                        let max_sps: f64 = *buffer
-                            .iter()
-                            .max_by(|a, b| a.partial_cmp(b).unwrap())
-                            .unwrap_or(&sps);
-
-                        println!(
-                            "{} FPS : {} SPS : {} BPS (AVG SPS: {:.2})",
-                            refresh_rate.ceil(),
-                            sps,
-                            bytes_per_second.ceil(),
-                            max_sps
-                        );
-
-                        // Emit the average instead of raw value
-                        let _ = app_handle.emit("samplerate", max_sps);
-
-                        packet_size = 0;
-                        sample_size = 0;
-                        data_size = 0;
-                        start_time = Instant::now();
-                    }
-                    packet_size += 1;
-
-                    for block_location in (0..data.len()).step_by(block_size) {
-                        sample_size += 1;
-                        let block = &data[block_location..block_location + block_size];
-                        let sample_number = block[0];
-                        let mut channel_data = vec![];
-
-                        for channel in 0..3 {
-                            let offset = 1 + (channel * 2);
-                            let sample = i16::from_be_bytes([block[offset], block[offset + 1]]);
-                            channel_data.push(sample as f32);
-                        }
-                        sample_count += 1;
-                        if let Some(prev) = previous_sample_number {
-                            if sample_number.wrapping_sub(prev) > 1 {
-                                println!("Error: Sample Lost");
-                                samplelost += 1;
-                                let _ = app_handle.emit("samplelost", samplelost);
-                                break;
-                            } else if sample_number == prev {
-                                println!("Error: Duplicate Sample");
-                                break;
+        let stats = pipeline.stats();
+        let mut tap = Tap::new(&pipeline, NPG_WIFI);
+        let spec = pipeline.spec();
+        let (mut producer, mut consumer) =
+            ring::ring(spec.channels, (spec.sample_rate * RING_SECONDS) as usize);
+
+        thread::spawn(move || {
+            let mut decoder = NPG_WIFI.decoder();
+            let mut last_print_time = Instant::now();
+
+            loop {
+                match socket.read_message() {
+                    Ok(Message::Binary(data)) => {
+                        tap.record(&data);
+                        decoder.feed(&data, &stats, |sample| {
+                            if !producer.push(sample) {
+                                stats.add_overflow();
                             }
-                        }
-
-                        previous_sample_number = Some(sample_number);
-                        previous_data = channel_data.clone();
+                        });
 
-                        // println!("EEG Data: {} {:?}", sample_number, channel_data);
-                        if let Err(err) = outlet.push_sample(&channel_data) {
-                            eprintln!("Failed to push sample: {}", err);
-                        }
-
-                        let _ = app_handle.emit("connection", "Connected");
-
-                        if sample_count % 100 == 0 {
-                            let elapsed = start_time.elapsed().as_secs_f64();
-                            let rate = sample_count as f64 / elapsed;
-
-                            let _ = app_handle.emit("lsl", "uidwifi007");
+                        if last_print_time.elapsed() >= Duration::from_secs(1) {
+                            let _ = app_handle.emit("connection", "Connected");
+                            let _ = app_handle.emit("lsl", WIFI_SESSION);
+                            last_print_time = Instant::now();
                         }
                     }
-                }
-                Ok(_) => {} // Ignore other messages
-                Err(e) => {
-                    eprintln!("WebSocket error: {:?}", e);
-                    break;
+                    Ok(_) => {} // Ignore other messages
+                    Err(e) => {
+                        eprintln!("WebSocket error: {:?}", e);
+                        break;
+                    }
                 }
             }
-            thread::sleep(Duration::from_millis(1));
-        }
+        });
+        publish(&mut consumer, &mut pipeline);
     });
 }
 use btleplug::api::{Central, Manager as _, Peripheral as _, ScanFilter};
 use btleplug::platform::Manager as BtleManager;
 
-// Thread-safe wrapper for StreamOutlet
-struct SafeOutlet(Option<StreamOutlet>);
-unsafe impl Send for SafeOutlet {}
-unsafe impl Sync for SafeOutlet {}
-
-// Constants for BLE communication
-const SINGLE_SAMPLE_LEN: usize = 7;
-const BLOCK_COUNT: usize = 10;
-const NEW_PACKET_LEN: usize = SINGLE_SAMPLE_LEN * BLOCK_COUNT;
-
 // Global state for BLE
 lazy_static! {
-    static ref BLE_OUTLET: Arc<Mutex<SafeOutlet>> = Arc::new(Mutex::new(SafeOutlet(None)));
-    static ref BLE_SAMPLE_COUNTER: AtomicU8 = AtomicU8::new(0);
     static ref BLE_CONNECTED: Arc<Mutex<bool>> = Arc::new(Mutex::new(false));
 }
-
-// Create BLE LSL outlet
-fn create_ble_outlet() -> Result<(), String> {
-    let mut info = StreamInfo::new(
-        "NPG-Lite",
-        "EXG",
-        3,
-        500.0,
-        ChannelFormat::Float32,
-        "uidbluetooth007",
-    )
-    .map_err(|e| e.to_string())?;
-
-    let mut desc = info.desc();
-    let mut resinfo = desc.append_child("resinfo");
-    resinfo.append_child_value("resolution", "12");
-
-    // Debug XML
-    match info.to_xml() {
-        Ok(xml) => println!("✅ Final LSL StreamInfo:\n{}", xml),
-        Err(e) => println!("❌ XML error: {}", e),
-    }
-    println!("[DEBUG] StreamInfo XML:\n{}", info.to_xml().unwrap());
-
-    let outlet = StreamOutlet::new(&info, 0, 360).map_err(|e| e.to_string())?;
-    *BLE_OUTLET.lock().unwrap() = SafeOutlet(Some(outlet));
-    Ok(())
+// Bumped by every connect and close; a notification task runs while it holds
+// the latest value.
+static BLE_GENERATION: AtomicU64 = AtomicU64::new(0);
+// How often a quiet notification task checks whether it should stop.
+const BLE_STOP_CHECK: Duration = Duration::from_millis(250);
+
+// Create BLE LSL outlet. The pipeline lives on a thread of its own, fed by
+// the notification task through the returned ring, and closes once the task
+// drops it.
+fn create_ble_outlet(
+    output: &OutputConfig,
+    app_handle: AppHandle,
+) -> Result<(Arc<StreamStats>, Tap, Producer), String> {
+    let spec = StreamSpec {
+        name: "NPG-Lite".into(),
+        stream_type: "EXG".into(),
+        channels: 3,
+        sample_rate: 500.0,
+        source_id: "uidbluetooth007".into(),
+    };
+    let scaling = Scaling::new(12, 3.3).with_config(output);
+    let mode = output.mode.unwrap_or(OutputMode::Raw);
+    let raw_format = output.raw_format.unwrap_or(RawFormat::Float32);
+    let (producer, mut consumer) =
+        ring::ring(spec.channels, (spec.sample_rate * RING_SECONDS) as usize);
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let pipeline = Pipeline::new(BLE_SESSION, spec, scaling, mode, raw_format, app_handle);
+        let mut pipeline = match pipeline {
+            Ok(pipeline) => pipeline,
+            Err(e) => {
+                let _ = sender.send(Err(e));
+                return;
+            }
+        };
+        let _ = sender.send(Ok((pipeline.stats(), Tap::new(&pipeline, NPG_BLE))));
+        publish(&mut consumer, &mut pipeline);
+    });
+    let (stats, tap) = receiver.recv().map_err(|e| e.to_string())??;
+    Ok((stats, tap, producer))
 }
 
 // Close BLE LSL outlet
 fn close_ble_outlet() {
-    *BLE_OUTLET.lock().unwrap() = SafeOutlet(None);
-    BLE_SAMPLE_COUNTER.store(0, Ordering::Relaxed);
+    BLE_GENERATION.fetch_add(1, Ordering::SeqCst);
     *BLE_CONNECTED.lock().unwrap() = false;
 }
 
-// Process BLE samples
-fn process_ble_sample(sample: &[u8], app_handle: AppHandle) -> Result<Vec<f32>, String> {
-    if sample.len() != SINGLE_SAMPLE_LEN {
-        return Err("Invalid sample length".to_string());
-    }
-    let mut samplelost = 0;
-    let sample_counter = sample[0];
-    let prev = BLE_SAMPLE_COUNTER.load(Ordering::Relaxed);
-    let expected = prev.wrapping_add(1);
-    if sample_counter != expected {
-        samplelost += 1;
-
-        println!(
-            "Sample counter discontinuity: expected {}, got {}",
-            expected, sample_counter
-        );
-    }
-    BLE_SAMPLE_COUNTER.store(sample_counter, Ordering::Relaxed);
-    let _ = app_handle.emit("samplelost", samplelost);
-
-    Ok(vec![
-        i16::from_be_bytes([sample[1], sample[2]]) as f32,
-        i16::from_be_bytes([sample[3], sample[4]]) as f32,
-        i16::from_be_bytes([sample[5], sample[6]]) as f32,
-    ])
-}
-
 #[tauri::command]
 async fn scan_ble_devices(app_handle: AppHandle) -> Result<(), String> {
     let manager = BtleManager::new()
@@ -596,11 +582,14 @@ async fn scan_ble_devices(app_handle: AppHandle) -> Result<(), String> {
 }
 
 #[tauri::command]
-async fn connect_to_ble(device_id: String, app_handle: AppHandle) -> Result<String, String> {
+async fn connect_to_ble(
+    device_id: String,
+    output: Option<OutputConfig>,
+    app_handle: AppHandle,
+) -> Result<String, String> {
     println!("[CONNECT] Starting connection to device: {}", device_id);
     close_ble_outlet();
-    const BUFFER_SIZE: usize = 20;
-    let mut buffer: VecDeque<f64> = VecDeque::with_capacity(BUFFER_SIZE);
+    let output = output.unwrap_or_default();
 
     // 1. Initialize Bluetooth Manager
     let manager = match BtleManager::new().await {
@@ -751,10 +740,15 @@ async fn connect_to_ble(device_id: String, app_handle: AppHandle) -> Result<Stri
 
                 println!("[LSL] Creating outlet...");
 
-                if let Err(e) = create_ble_outlet() {
-                    println!("[ERROR] Outlet creation failed: {}", e);
-                    return Err(format!("LSL initialization failed: {}", e));
-                }
+                let generation = BLE_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+                let (stats, mut tap, mut producer) =
+                    match create_ble_outlet(&output, app_handle.clone()) {
+                        Ok(outlet) => outlet,
+                        Err(e) => {
+                            println!("[ERROR] Outlet creation failed: {}", e);
+                            return Err(format!("LSL initialization failed: {}", e));
+                        }
+                    };
 
                 // 9. Connect with timeout (10 seconds)
                 println!("[CONNECT] Attempting connection...");
@@ -837,128 +831,48 @@ async fn connect_to_ble(device_id: String, app_handle: AppHandle) -> Result<Stri
 
                 tokio::spawn(async move {
                     println!("[TASK] Starting data processing loop");
-                    const EXPECTED_SAMPLE_RATE: f64 = 500.0; // Fixed expected sample rate
-                    const SAMPLES_PER_SECOND: usize = EXPECTED_SAMPLE_RATE as usize;
-                    const BUFFER_SIZE: usize = 20;
-
-                    let mut sample_count = 0;
-                    let mut packet_count = 0;
-                    let mut lost_samples = 0;
-                    let mut last_sample_number: Option<u8> = None;
-                    let start_time = Instant::now();
+                    let mut decoder = NPG_BLE.decoder();
                     let mut last_print_time = Instant::now();
-                    let mut buffer: VecDeque<f64> = VecDeque::with_capacity(BUFFER_SIZE);
-
-                    while *BLE_CONNECTED.lock().unwrap() {
-                        if let Some(data) = notifications.next().await {
-                            packet_count += 1;
-
-                            match data.value.len() {
-                                NEW_PACKET_LEN => {
-                                    for chunk in data.value.chunks_exact(SINGLE_SAMPLE_LEN) {
-                                        if let Ok(sample) =
-                                            process_ble_sample(chunk, app_handle_clone.clone())
-                                        {
-                                            // Check sample counter continuity
-                                            let current_sample_number = chunk[0];
-                                            if let Some(last) = last_sample_number {
-                                                let expected = last.wrapping_add(1);
-                                                if current_sample_number != expected {
-                                                    lost_samples += current_sample_number
-                                                        .wrapping_sub(expected)
-                                                        as usize;
-                                                    println!(
-                                                        "Lost {} samples",
-                                                        current_sample_number
-                                                            .wrapping_sub(expected)
-                                                    );
-                                                }
-                                            }
-                                            last_sample_number = Some(current_sample_number);
-
-                                            sample_count += 1;
-
-                                            // Push to LSL
-                                            if let Some(outlet) = &BLE_OUTLET.lock().unwrap().0 {
-                                                if let Err(e) = outlet.push_sample(&sample) {
-                                                    println!("[LSL] Push error: {}", e);
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                                SINGLE_SAMPLE_LEN => {
-                                    if let Ok(sample) =
-                                        process_ble_sample(&data.value, app_handle_clone.clone())
-                                    {
-                                        // Check sample counter continuity
-                                        let current_sample_number = data.value[0];
-                                        if let Some(last) = last_sample_number {
-                                            let expected = last.wrapping_add(1);
-                                            if current_sample_number != expected {
-                                                lost_samples += current_sample_number
-                                                    .wrapping_sub(expected)
-                                                    as usize;
-                                                println!(
-                                                    "Lost {} samples",
-                                                    current_sample_number.wrapping_sub(expected)
-                                                );
-                                            }
-                                        }
-                                        last_sample_number = Some(current_sample_number);
-
-                                        sample_count += 1;
-
-                                        if let Some(outlet) = &BLE_OUTLET.lock().unwrap().0 {
-                                            if let Err(e) = outlet.push_sample(&sample) {
-                                                println!("[LSL] Push error: {}", e);
-                                            }
-                                        }
-                                    }
-                                }
-                                len => println!("[WARN] Unexpected packet length: {}", len),
-                            }
 
-                            // Calculate statistics every second
-                            let elapsed = last_print_time.elapsed().as_secs_f64();
-                            if elapsed >= 1.0 {
-                                // Calculate actual sample rate (should be close to 500)
-                                let actual_rate = sample_count as f64 / elapsed;
-
-                                // Calculate percentage of expected samples received
-                                let expected_samples = (EXPECTED_SAMPLE_RATE * elapsed) as usize;
-                                let received_percentage =
-                                    (sample_count as f64 / expected_samples as f64) * 100.0;
-
-                                // Maintain buffer for smoothing
-                                if buffer.len() == BUFFER_SIZE {
-                                    buffer.pop_front();
-                                }
-                                buffer.push_back(received_percentage);
-
-                                // Calculate average reception percentage
-                                let avg_percentage: f64 =
-                                    buffer.iter().sum::<f64>() / buffer.len() as f64;
-
-                                // Emit quality metrics to frontend
-                                let _ = app_handle_clone.emit("samplerate", actual_rate);
-                                let _ = app_handle_clone.emit("samplelost", lost_samples);
-                                let _ = app_handle_clone.emit("lsl", "uidbluetooth007");
-
-                                // Reset counters
-                                sample_count = 0;
-                                lost_samples = 0;
-                                last_print_time = Instant::now();
+                    while *BLE_CONNECTED.lock().unwrap()
+                        && BLE_GENERATION.load(Ordering::SeqCst) == generation
+                    {
+                        let data = match tokio::time::timeout(BLE_STOP_CHECK, notifications.next())
+                            .await
+                        {
+                            Ok(Some(data)) => data,
+                            Ok(None) => {
+                                println!("[TASK] Notification stream ended");
+                                break;
                             }
-                        } else {
-                            println!("[TASK] Notification stream ended");
-                            break;
+                            Err(_) => continue,
+                        };
+                        tap.record(&data.value);
+                        // Queue for the LSL thread
+                        decoder.feed(&data.value, &stats, |sample| {
+                            if !producer.push(sample) {
+                                stats.add_overflow();
+                            }
+                        });
+
+                        if last_print_time.elapsed() >= Duration::from_secs(1) {
+                            let _ = app_handle_clone.emit("lsl", BLE_SESSION);
+                            last_print_time = Instant::now();
                         }
                     }
 
                     println!("[TASK] Cleaning up...");
-                    close_ble_outlet();
+                    // A newer connection may have taken over by now.
+                    if BLE_GENERATION.load(Ordering::SeqCst) == generation {
+                        close_ble_outlet();
+                    }
                 });
+                last_device::remember(
+                    &app_handle,
+                    LastDevice::Ble {
+                        id: device_id.clone(),
+                    },
+                );
                 return Ok(format!("Connected"));
             }
         }
@@ -977,6 +891,135 @@ fn cleanup_resources() {
 fn cleanup_ble() {
     close_ble_outlet();
 }
+
+#[tauri::command]
+fn set_filter_config(session_id: String, config: Option<FilterConfig>) {
+    session::get_or_create(&session_id).update(|settings| settings.filter = config);
+}
+
+#[tauri::command]
+fn set_quality_config(session_id: String, config: Option<QualityConfig>) -> Result<(), String> {
+    if let Some(config) = &config {
+        config.validate()?;
+    }
+    session::get_or_create(&session_id).update(|settings| settings.quality = config);
+    Ok(())
+}
+
+#[tauri::command]
+fn set_ecg_config(session_id: String, config: Option<EcgConfig>) -> Result<(), String> {
+    let session = session::get_or_create(&session_id);
+    // Before the session's first stream it's checked once that starts.
+    if let (Some(config), Some(shape)) = (&config, session.shape()) {
+        config.validate(shape.channels)?;
+    }
+    session.update(|settings| settings.ecg = config);
+    Ok(())
+}
+
+#[tauri::command]
+fn set_emg_config(session_id: String, config: Option<EmgConfig>) {
+    session::get_or_create(&session_id).update(|settings| settings.emg = config);
+}
+
+#[tauri::command]
+fn set_band_power_config(
+    session_id: String,
+    config: Option<BandPowerConfig>,
+) -> Result<(), String> {
+    let session = session::get_or_create(&session_id);
+    // Before the session's first stream it's checked once that starts.
+    if let (Some(config), Some(shape)) = (&config, session.shape()) {
+        BandPower::new(config.clone(), shape.channels, shape.sample_rate)?;
+    }
+    session.update(|settings| settings.band_power = config);
+    Ok(())
+}
+
+#[tauri::command]
+fn set_preview_config(session_id: String, config: Option<PreviewConfig>) {
+    session::get_or_create(&session_id).update(|settings| settings.preview = config);
+}
+
+#[tauri::command]
+fn set_spectrum_config(session_id: String, config: Option<SpectrumConfig>) {
+    session::get_or_create(&session_id).update(|settings| settings.spectrum = config);
+}
+
+#[tauri::command]
+fn set_osc_config(session_id: String, config: Option<OscConfig>) {
+    session::get_or_create(&session_id).update(|settings| settings.osc = config);
+}
+
+#[tauri::command]
+fn set_mqtt_config(session_id: String, config: Option<MqttConfig>) {
+    session::get_or_create(&session_id).update(|settings| settings.mqtt = config);
+}
+
+#[tauri::command]
+fn set_capture(session_id: String, path: Option<String>) {
+    session::get_or_create(&session_id).update(|settings| settings.capture = path);
+}
+
+#[tauri::command]
+fn start_replay(
+    path: String,
+    realtime: Option<bool>,
+    output: Option<OutputConfig>,
+    app_handle: AppHandle,
+) -> Result<(), String> {
+    replay::start(
+        &path,
+        realtime.unwrap_or(true),
+        &output.unwrap_or_default(),
+        app_handle,
+    )
+}
+
+#[tauri::command]
+fn stop_replay() {
+    replay::stop();
+}
+
+#[tauri::command]
+fn start_playback(
+    path: String,
+    options: Option<PlaybackOptions>,
+    output: Option<OutputConfig>,
+    app_handle: AppHandle,
+) -> Result<PlaybackInfo, String> {
+    let (spec, scaling) = serial_stream(&BOARD.lock().unwrap());
+    playback::start(
+        &path,
+        spec,
+        scaling,
+        &options.unwrap_or_default(),
+        &output.unwrap_or_default(),
+        app_handle,
+    )
+}
+
+#[tauri::command]
+fn set_playback_controls(speed: Option<f64>, looped: Option<bool>) {
+    playback::set_controls(speed, looped);
+}
+
+#[tauri::command]
+fn stop_playback() {
+    playback::stop();
+}
+
+#[tauri::command]
+fn start_broadcast(port: Option<u16>, origins: Option<Vec<String>>) -> Result<u16, String> {
+    let port = port.unwrap_or(broadcast::DEFAULT_PORT);
+    broadcast::start(port, origins.unwrap_or_default())?;
+    Ok(port)
+}
+
+#[tauri::command]
+fn stop_broadcast() {
+    broadcast::stop();
+}
 // Add this with your other lazy_static declarations
 lazy_static! {
     // ... your existing static refs ...
@@ -1003,13 +1046,39 @@ fn main() {
     tauri::Builder::default()
         .invoke_handler(tauri::generate_handler![
             detect_arduino,
+            cancel_detection,
             scan_ble_devices,
             connect_to_ble,
             start_streaming,
+            start_streaming_manual,
+            list_serial_ports,
+            get_last_device,
+            set_auto_start,
+            find_last_serial_device,
+            list_board_profiles,
             start_wifistreaming,
             cleanup_ble,
+            set_filter_config,
+            set_quality_config,
+            set_ecg_config,
+            set_emg_config,
+            set_band_power_config,
+            set_preview_config,
+            set_spectrum_config,
+            set_osc_config,
+            set_mqtt_config,
+            set_capture,
+            start_replay,
+            stop_replay,
+            start_playback,
+            set_playback_controls,
+            stop_playback,
+            start_broadcast,
+            stop_broadcast,
         ])
         .setup(|app| {
+            hotplug::start(app.handle().clone());
+
             // Get the main window
             let window = app.get_webview_window("main").unwrap();
 
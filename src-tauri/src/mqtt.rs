@@ -0,0 +1,206 @@
+use crate::stats::{StatsSampler, StreamStats};
+use rumqttc::{Client, MqttOptions, QoS};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Settings of a session's MQTT sink.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    /// Defaults to `chords-<session>`.
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// 0, 1 or 2.
+    pub qos: u8,
+    /// `{session}` is filled in.
+    pub samples_topic: String,
+    /// Where the `stream_stats` report goes every second; off if empty.
+    pub stats_topic: String,
+    /// Samples per message.
+    pub batch: usize,
+    /// Publish microvolts instead of ADC counts.
+    pub microvolts: bool,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            host: "localhost".to_string(),
+            port: 1883,
+            client_id: None,
+            username: None,
+            password: None,
+            qos: 0,
+            samples_topic: "chords/{session}/samples".to_string(),
+            stats_topic: "chords/{session}/stats".to_string(),
+            batch: 50,
+            microvolts: false,
+        }
+    }
+}
+
+// Messages the client queues while the broker is unreachable; newer ones are
+// dropped beyond that.
+const QUEUE: usize = 64;
+
+/// Publishes a session's samples and stats to an MQTT broker. Connecting and
+/// reconnecting happen on a thread of its own, so a missing broker never
+/// holds up the pipeline.
+pub struct MqttSink {
+    config: MqttConfig,
+    client: Client,
+    qos: QoS,
+    samples_topic: String,
+    stats_topic: String,
+    sample_rate: f64,
+    /// JSON arrays of the samples batched so far, comma-separated; reused
+    /// from batch to batch.
+    samples: String,
+    batched: usize,
+    sampler: StatsSampler,
+    dropped: u64,
+    /// Tells the connection thread to stop; it may be stuck reconnecting.
+    stopped: Arc<AtomicBool>,
+}
+
+impl MqttSink {
+    pub fn new(config: MqttConfig, session: &str, sample_rate: f64) -> Result<Self, String> {
+        let qos = rumqttc::qos(config.qos).map_err(|e| format!("Invalid MQTT QoS: {:?}", e))?;
+        let client_id = config
+            .client_id
+            .clone()
+            .unwrap_or_else(|| format!("chords-{}", session));
+        let mut options = MqttOptions::new(client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(10));
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.as_deref().unwrap_or(""));
+        }
+
+        let (client, mut connection) = Client::new(options, QUEUE);
+        let broker = format!("{}:{}", config.host, config.port);
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stop = stopped.clone();
+        thread::spawn(move || {
+            // Only changes of the connection state are logged.
+            let mut connected = None;
+            // The iterator keeps retrying an unreachable broker forever, so
+            // the sink has to say when to give up.
+            for event in connection.iter() {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                match event {
+                    Ok(_) => {
+                        if connected != Some(true) {
+                            println!("Connected to MQTT broker {}", broker);
+                            connected = Some(true);
+                        }
+                    }
+                    Err(e) => {
+                        if connected != Some(false) {
+                            println!("MQTT broker {} unreachable, retrying: {}", broker, e);
+                            connected = Some(false);
+                        }
+                        thread::sleep(Duration::from_secs(1));
+                    }
+                }
+            }
+        });
+
+        Ok(MqttSink {
+            samples_topic: config.samples_topic.replace("{session}", session),
+            stats_topic: config.stats_topic.replace("{session}", session),
+            sampler: StatsSampler::new(session, sample_rate),
+            samples: String::new(),
+            batched: 0,
+            qos,
+            client,
+            sample_rate,
+            dropped: 0,
+            stopped,
+            config,
+        })
+    }
+
+    pub fn config(&self) -> &MqttConfig {
+        &self.config
+    }
+
+    /// Adds one sample, publishing a message once a batch is full, and the
+    /// stats once a second.
+    pub fn push(&mut self, counts: &[i32], microvolts: &[f32], stats: &StreamStats) {
+        if self.batched > 0 {
+            self.samples.push(',');
+        }
+        self.samples.push('[');
+        if self.config.microvolts {
+            append_values(&mut self.samples, microvolts);
+        } else {
+            append_values(&mut self.samples, counts);
+        }
+        self.samples.push(']');
+        self.batched += 1;
+
+        if self.batched >= self.config.batch.max(1) {
+            let unit = if self.config.microvolts {
+                "microvolts"
+            } else {
+                "counts"
+            };
+            let message = format!(
+                r#"{{"timestamp":{},"sampleRate":{},"unit":"{}","samples":[{}]}}"#,
+                lsl::local_clock(),
+                self.sample_rate,
+                unit,
+                self.samples,
+            );
+            self.samples.clear();
+            self.batched = 0;
+            self.publish(self.samples_topic.clone(), message);
+        }
+
+        if !self.stats_topic.is_empty() && self.sampler.elapsed() >= Duration::from_secs(1) {
+            let report = self.sampler.report(stats);
+            if let Ok(message) = serde_json::to_string(&report) {
+                self.publish(self.stats_topic.clone(), message);
+            }
+        }
+    }
+
+    fn publish(&mut self, topic: String, payload: String) {
+        if self
+            .client
+            .try_publish(topic, self.qos, false, payload)
+            .is_err()
+        {
+            self.dropped += 1;
+            if self.dropped.is_power_of_two() {
+                println!("MQTT queue full, {} messages dropped", self.dropped);
+            }
+        }
+    }
+}
+
+impl Drop for MqttSink {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        let _ = self.client.try_disconnect();
+    }
+}
+
+// Appends `values` as the comma-separated elements of a JSON array.
+fn append_values<T: Display>(out: &mut String, values: &[T]) {
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{}", value);
+    }
+}
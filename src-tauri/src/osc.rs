@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+
+/// Which values of a session go out over OSC.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OscSource {
+    /// ADC counts as int32 arguments.
+    #[default]
+    Raw,
+    Microvolts,
+    /// Output of the filter chain; nothing is sent while no filter is set.
+    Filtered,
+    /// EMG envelope; nothing is sent while EMG processing is off.
+    Envelope,
+    /// EEG band powers and ratios, one message per channel with a float per
+    /// value; nothing is sent while band power is off.
+    BandPower,
+}
+
+/// Settings of a session's OSC sink.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OscConfig {
+    pub host: String,
+    pub port: u16,
+    pub source: OscSource,
+    /// 1-based channels to send; empty means all of them.
+    pub channels: Vec<usize>,
+    /// `{session}` and `{n}` (1-based channel) are filled in.
+    pub address: String,
+    /// Bundles per second for per-sample sources; band power is sent whenever
+    /// a new estimate is ready.
+    pub rate: f64,
+}
+
+impl Default for OscConfig {
+    fn default() -> Self {
+        OscConfig {
+            host: "127.0.0.1".to_string(),
+            port: 9000,
+            source: OscSource::Raw,
+            channels: Vec::new(),
+            address: "/chords/{session}/ch/{n}".to_string(),
+            rate: 100.0,
+        }
+    }
+}
+
+/// Arguments of the messages in one bundle, `len / channels` per message.
+pub enum OscValues<'a> {
+    Counts(&'a [i32]),
+    Floats(&'a [f32]),
+}
+
+/// Sends a session's values as OSC bundles over UDP, one message per channel.
+pub struct OscSink {
+    config: OscConfig,
+    session: String,
+    /// The socket and the target it sends to, once the host is looked up.
+    link: Arc<OnceLock<(UdpSocket, SocketAddr)>>,
+    interval: usize,
+    since_send: usize,
+    // Encoded, padded address of each channel.
+    addresses: HashMap<usize, Vec<u8>>,
+    packet: Vec<u8>,
+}
+
+impl OscSink {
+    /// Looks the host up on a thread of its own, since a DNS lookup can take
+    /// seconds; nothing is sent until it's done.
+    pub fn new(config: OscConfig, session: &str, sample_rate: f64) -> Self {
+        let link = Arc::new(OnceLock::new());
+        let resolved = link.clone();
+        let (host, port) = (config.host.clone(), config.port);
+        thread::spawn(move || match open(&host, port) {
+            Ok(connected) => {
+                let _ = resolved.set(connected);
+            }
+            Err(e) => println!("Failed to create OSC sink: {}", e),
+        });
+
+        OscSink {
+            interval: ((sample_rate / config.rate.max(1.0)) as usize).max(1),
+            since_send: 0,
+            session: session.to_string(),
+            link,
+            addresses: HashMap::new(),
+            packet: Vec::new(),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &OscConfig {
+        &self.config
+    }
+
+    /// Counts a sample of a per-sample source; true when a bundle is due.
+    pub fn due(&mut self) -> bool {
+        self.since_send += 1;
+        if self.since_send < self.interval {
+            return false;
+        }
+        self.since_send = 0;
+        true
+    }
+
+    /// Sends one bundle. `channels[i]` is the 1-based channel of the i-th run
+    /// of values; channels not selected in the config are left out.
+    pub fn send(&mut self, channels: &[usize], values: OscValues) {
+        let len = match values {
+            OscValues::Counts(v) => v.len(),
+            OscValues::Floats(v) => v.len(),
+        };
+        let Some((socket, target)) = self.link.get() else {
+            return;
+        };
+        if channels.is_empty() {
+            return;
+        }
+        let per_channel = len / channels.len();
+
+        self.packet.clear();
+        self.packet.extend_from_slice(b"#bundle\0");
+        // Time tag 1 means "immediately".
+        self.packet.extend_from_slice(&1u64.to_be_bytes());
+
+        let mut messages = 0;
+        for (i, &channel) in channels.iter().enumerate() {
+            if !self.config.channels.is_empty() && !self.config.channels.contains(&channel) {
+                continue;
+            }
+            messages += 1;
+            let address = self.addresses.entry(channel).or_insert_with(|| {
+                let address = self
+                    .config
+                    .address
+                    .replace("{session}", &self.session)
+                    .replace("{n}", &channel.to_string());
+                padded(address.as_bytes())
+            });
+
+            let tag = match values {
+                OscValues::Counts(_) => b'i',
+                OscValues::Floats(_) => b'f',
+            };
+            let mut tags = vec![b','];
+            tags.resize(per_channel + 1, tag);
+            let tags = padded(&tags);
+
+            let size = address.len() + tags.len() + per_channel * 4;
+            self.packet.extend_from_slice(&(size as i32).to_be_bytes());
+            self.packet.extend_from_slice(address);
+            self.packet.extend_from_slice(&tags);
+            let range = i * per_channel..(i + 1) * per_channel;
+            match values {
+                OscValues::Counts(v) => {
+                    for &count in &v[range] {
+                        self.packet.extend_from_slice(&count.to_be_bytes());
+                    }
+                }
+                OscValues::Floats(v) => {
+                    for &value in &v[range] {
+                        self.packet.extend_from_slice(&value.to_be_bytes());
+                    }
+                }
+            }
+        }
+
+        // None of the selected channels are in this run.
+        if messages == 0 {
+            return;
+        }
+        // Dropped datagrams are fine for live control data.
+        let _ = socket.send_to(&self.packet, target);
+    }
+}
+
+// Resolves `host` and binds a socket of the matching address family.
+fn open(host: &str, port: u16) -> Result<(UdpSocket, SocketAddr), String> {
+    let target = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("Invalid OSC target {}: {}", host, e))?
+        .next()
+        .ok_or_else(|| format!("Invalid OSC target {}", host))?;
+    let socket = UdpSocket::bind(if target.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    })
+    .map_err(|e| e.to_string())?;
+    // A dead receiver must never hold up the pipeline.
+    socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+    Ok((socket, target))
+}
+
+/// OSC strings are NUL-terminated and padded to a multiple of four bytes.
+fn padded(bytes: &[u8]) -> Vec<u8> {
+    let mut out = bytes.to_vec();
+    out.resize((bytes.len() / 4 + 1) * 4, 0);
+    out
+}
@@ -0,0 +1,176 @@
+use crate::scaling::{OutputMode, RawFormat, Scaling};
+use lsl::Pushable;
+use lsl::{ChannelFormat, StreamInfo, StreamOutlet};
+use serde::{Deserialize, Serialize};
+
+/// Everything needed to describe a session's LSL stream.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamSpec {
+    pub name: String,
+    pub stream_type: String,
+    pub channels: usize,
+    pub sample_rate: f64,
+    pub source_id: String,
+}
+
+impl StreamSpec {
+    /// Builds a `StreamInfo` for this stream. `suffix` is appended to both the
+    /// name and the source id so derived outlets don't collide with the main one.
+    pub fn stream_info(
+        &self,
+        suffix: &str,
+        format: ChannelFormat,
+        scaling: &Scaling,
+        unit: &str,
+    ) -> Result<StreamInfo, String> {
+        let mut info = StreamInfo::new(
+            &format!("{}{}", self.name, suffix),
+            &self.stream_type,
+            self.channels as u32,
+            self.sample_rate,
+            format,
+            &format!("{}{}", self.source_id, suffix),
+        )
+        .map_err(|e| e.to_string())?;
+
+        let mut desc = info.desc();
+        let mut resinfo = desc.append_child("resinfo");
+        resinfo.append_child_value("resolution", &scaling.resolution.to_string());
+        resinfo.append_child_value("vref", &scaling.vref.to_string());
+        resinfo.append_child_value("gain", &scaling.gain.to_string());
+        resinfo.append_child_value("offset", &scaling.offset().to_string());
+        resinfo.append_child_value("uv_per_count", &scaling.microvolts_per_count().to_string());
+
+        let mut channels = desc.append_child("channels");
+        for i in 0..self.channels {
+            let mut channel = channels.append_child("channel");
+            channel.append_child_value("label", &format!("CH{}", i + 1));
+            channel.append_child_value("unit", unit);
+        }
+
+        Ok(info)
+    }
+
+    /// Builds a `StreamInfo` for a value derived from this stream, such as
+    /// quality metrics or heart rate, with its own labels and rate.
+    pub fn derived_info(
+        &self,
+        suffix: &str,
+        stream_type: &str,
+        sample_rate: f64,
+        format: ChannelFormat,
+        labels: &[String],
+        unit: &str,
+    ) -> Result<StreamInfo, String> {
+        let mut info = StreamInfo::new(
+            &format!("{}{}", self.name, suffix),
+            stream_type,
+            labels.len() as u32,
+            sample_rate,
+            format,
+            &format!("{}{}", self.source_id, suffix),
+        )
+        .map_err(|e| e.to_string())?;
+
+        let mut desc = info.desc();
+        desc.append_child_value("derived_from", &self.name);
+
+        let mut channels = desc.append_child("channels");
+        for label in labels {
+            let mut channel = channels.append_child("channel");
+            channel.append_child_value("label", label);
+            channel.append_child_value("unit", unit);
+        }
+
+        Ok(info)
+    }
+}
+
+/// The raw and/or scaled outlets of one session.
+pub struct OutletSet {
+    raw: Option<StreamOutlet>,
+    scaled: Option<StreamOutlet>,
+    scaling: Scaling,
+    raw_format: RawFormat,
+}
+
+impl OutletSet {
+    pub fn new(
+        spec: &StreamSpec,
+        scaling: Scaling,
+        mode: OutputMode,
+        raw_format: RawFormat,
+    ) -> Result<Self, String> {
+        let raw = if mode.has_raw() {
+            let format = match raw_format {
+                RawFormat::Int16 if scaling.resolution > 16 => {
+                    return Err(format!(
+                        "Int16 can't hold the counts of a {}-bit ADC",
+                        scaling.resolution
+                    ))
+                }
+                RawFormat::Int16 => ChannelFormat::Int16,
+                RawFormat::Int32 => ChannelFormat::Int32,
+                RawFormat::Float32 => ChannelFormat::Float32,
+            };
+            let info = spec.stream_info("", format, &scaling, "counts")?;
+            Some(StreamOutlet::new(&info, 0, 360).map_err(|e| e.to_string())?)
+        } else {
+            None
+        };
+
+        let scaled = if mode.has_scaled() {
+            // When only the scaled outlet exists it takes over the plain name.
+            let suffix = if mode == OutputMode::Both { "_uV" } else { "" };
+            let info = spec.stream_info(suffix, ChannelFormat::Float32, &scaling, "microvolts")?;
+            Some(StreamOutlet::new(&info, 0, 360).map_err(|e| e.to_string())?)
+        } else {
+            None
+        };
+
+        Ok(OutletSet {
+            raw,
+            scaled,
+            scaling,
+            raw_format,
+        })
+    }
+
+    pub fn scaling(&self) -> &Scaling {
+        &self.scaling
+    }
+
+    pub fn push(&self, counts: &[i32]) {
+        if let Some(outlet) = &self.raw {
+            let result = match self.raw_format {
+                RawFormat::Int16 => {
+                    outlet.push_sample(&counts.iter().map(|&c| c as i16).collect::<Vec<_>>())
+                }
+                RawFormat::Int32 => outlet.push_sample(&counts.to_vec()),
+                RawFormat::Float32 => {
+                    outlet.push_sample(&counts.iter().map(|&c| c as f32).collect::<Vec<_>>())
+                }
+            };
+            if let Err(e) = result {
+                println!("Failed to push data to LSL: {:?}", e);
+            }
+        }
+        if self.scaled.is_some() {
+            let sample: Vec<f32> = counts
+                .iter()
+                .map(|&c| self.scaling.to_microvolts(c))
+                .collect();
+            self.push_scaled(&sample);
+        }
+    }
+
+    /// Publishes a sample already in microvolts on the scaled outlet only.
+    pub fn push_scaled(&self, microvolts: &[f32]) {
+        if let Some(outlet) = &self.scaled {
+            if let Err(e) = outlet.push_sample(&microvolts.to_vec()) {
+                println!("Failed to push data to LSL: {:?}", e);
+            }
+        }
+    }
+}
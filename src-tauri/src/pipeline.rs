@@ -0,0 +1,725 @@
+use crate::broadcast::Publisher;
+use crate::ecg::{EcgConfig, QrsDetector};
+use crate::eeg::BandPower;
+use crate::emg::{Activation, EmgProcessor};
+use crate::filter::{FilterChain, FilterConfig};
+use crate::mqtt::MqttSink;
+use crate::osc::{OscSink, OscSource, OscValues};
+use crate::outlet::{OutletSet, StreamSpec};
+use crate::preview::PreviewFeed;
+use crate::quality::{ChannelQuality, QualityConfig, QualityMonitor};
+use crate::scaling::{OutputMode, RawFormat, Scaling};
+use crate::session::{self, Session, SessionSettings, StreamShape};
+use crate::spectrum_feed::SpectrumFeed;
+use crate::stats::{self, JitterMeter, StreamStats};
+use lsl::{ChannelFormat, StreamOutlet};
+use lsl::{ExPushable, Pushable};
+use serde::Serialize;
+use std::sync::Arc;
+use tauri::{AppHandle, Emitter};
+
+/// The `*_filtered` outlet and the filter chain feeding it.
+struct FilterStage {
+    config: FilterConfig,
+    chain: FilterChain,
+    outlet: StreamOutlet,
+    buffer: Vec<f32>,
+}
+
+/// The quality monitor and its optional low-rate outlet.
+struct QualityStage {
+    monitor: QualityMonitor,
+    outlet: Option<StreamOutlet>,
+}
+
+/// Payload of the `channel_quality` event.
+#[derive(Clone, Serialize)]
+struct QualityReport<'a> {
+    session: &'a str,
+    channels: Vec<ChannelQuality>,
+}
+
+/// The QRS detector and its heart-rate and R-peak marker outlets.
+struct EcgStage {
+    config: EcgConfig,
+    detector: QrsDetector,
+    rate_outlet: StreamOutlet,
+    marker_outlet: StreamOutlet,
+}
+
+/// The EMG processor with its envelope and activation marker outlets.
+struct EmgStage {
+    processor: EmgProcessor,
+    envelope_outlet: StreamOutlet,
+    marker_outlet: StreamOutlet,
+    envelope: Vec<f32>,
+    activations: Vec<Activation>,
+}
+
+/// The EEG band-power estimator and its outlet.
+struct BandPowerStage {
+    band_power: BandPower,
+    outlet: StreamOutlet,
+}
+
+/// Payload of the `heart_rate` event.
+#[derive(Clone, Serialize)]
+struct HeartRate<'a> {
+    session: &'a str,
+    bpm: f64,
+    rr: f64,
+}
+
+/// Everything that happens to a decoded sample after it leaves the transport.
+/// Each transport owns one pipeline and pushes raw ADC counts into it.
+pub struct Pipeline {
+    app_handle: AppHandle,
+    session: Arc<Session>,
+    spec: StreamSpec,
+    outlets: OutletSet,
+    revision: u64,
+    filter: Option<FilterStage>,
+    quality: Option<QualityStage>,
+    ecg: Option<EcgStage>,
+    emg: Option<EmgStage>,
+    band_power: Option<BandPowerStage>,
+    preview: Option<PreviewFeed>,
+    spectrum: Option<SpectrumFeed>,
+    osc: Option<OscSink>,
+    mqtt: Option<MqttSink>,
+    stats: Arc<StreamStats>,
+    jitter: JitterMeter,
+    broadcast: Publisher,
+    // The current sample in microvolts, shared by all stages.
+    scaled: Vec<f32>,
+}
+
+impl Pipeline {
+    pub fn new(
+        session_id: &str,
+        spec: StreamSpec,
+        scaling: Scaling,
+        mode: OutputMode,
+        raw_format: RawFormat,
+        app_handle: AppHandle,
+    ) -> Result<Self, String> {
+        let outlets = OutletSet::new(&spec, scaling, mode, raw_format)?;
+        let stats = Arc::new(StreamStats::default());
+        let jitter = JitterMeter::new(spec.sample_rate);
+        let broadcast = Publisher::new(
+            session_id,
+            spec.channels,
+            spec.sample_rate,
+            scaling.is_wide(),
+        );
+        stats::spawn_reporter(
+            stats.clone(),
+            session_id.to_string(),
+            spec.sample_rate,
+            app_handle.clone(),
+        );
+        let session = session::get_or_create(session_id);
+        session.set_shape(StreamShape {
+            channels: spec.channels,
+            sample_rate: spec.sample_rate,
+        });
+        Ok(Pipeline {
+            app_handle,
+            session,
+            scaled: vec![0.0; spec.channels],
+            spec,
+            outlets,
+            revision: 0,
+            filter: None,
+            quality: None,
+            ecg: None,
+            emg: None,
+            band_power: None,
+            preview: None,
+            spectrum: None,
+            osc: None,
+            mqtt: None,
+            jitter,
+            stats,
+            broadcast,
+        })
+    }
+
+    pub fn session(&self) -> Arc<Session> {
+        self.session.clone()
+    }
+
+    pub fn spec(&self) -> &StreamSpec {
+        &self.spec
+    }
+
+    pub fn scaling(&self) -> Scaling {
+        *self.outlets.scaling()
+    }
+
+    /// Counters of this session, shared with the transport feeding it.
+    pub fn stats(&self) -> Arc<StreamStats> {
+        self.stats.clone()
+    }
+
+    pub fn push(&mut self, counts: &[i32]) {
+        self.check_settings();
+        self.outlets.push(counts);
+        let scaling = *self.outlets.scaling();
+        for (out, &count) in self.scaled.iter_mut().zip(counts) {
+            *out = scaling.to_microvolts(count);
+        }
+        self.process(counts, true);
+    }
+
+    /// For sources that only have microvolts, such as recordings. They are
+    /// published as they are; sinks that want counts get them converted
+    /// back, and quality monitoring, which judges the ADC's own counts, is
+    /// skipped.
+    pub fn push_microvolts(&mut self, microvolts: &[f32]) {
+        self.check_settings();
+        self.outlets.push_scaled(microvolts);
+        self.scaled.copy_from_slice(microvolts);
+        let scaling = *self.outlets.scaling();
+        let counts: Vec<i32> = microvolts
+            .iter()
+            .map(|&uv| scaling.to_counts(uv as f64))
+            .collect();
+        self.process(&counts, false);
+    }
+
+    fn check_settings(&mut self) {
+        let revision = self.session.revision();
+        if revision != self.revision {
+            self.revision = revision;
+            self.apply_settings();
+        }
+    }
+
+    // Everything after the main outlets. `measured` is false when `counts`
+    // were derived from microvolts rather than read from an ADC.
+    fn process(&mut self, counts: &[i32], measured: bool) {
+        self.stats.add_delivered();
+        self.jitter.record(&self.stats);
+
+        self.broadcast.push(counts, &self.scaled);
+        if let Some(sink) = &mut self.mqtt {
+            sink.push(counts, &self.scaled, &self.stats);
+        }
+
+        if let Some(stage) = &mut self.filter {
+            stage.buffer.copy_from_slice(&self.scaled);
+            stage.chain.process(&mut stage.buffer);
+            if let Err(e) = stage.outlet.push_sample(&stage.buffer) {
+                println!("Failed to push filtered data to LSL: {:?}", e);
+            }
+        }
+
+        if let Some(feed) = &mut self.preview {
+            match &self.filter {
+                Some(stage) if feed.config().filtered => feed.push(&stage.buffer),
+                _ => feed.push(&self.scaled),
+            }
+        }
+
+        if let Some(feed) = &mut self.spectrum {
+            feed.push(&self.scaled);
+        }
+
+        if let Some(stage) = self.quality.as_mut().filter(|_| measured) {
+            if let Some(channels) = stage.monitor.push(counts) {
+                if let Some(outlet) = &stage.outlet {
+                    let values: Vec<f32> = channels
+                        .iter()
+                        .flat_map(|channel| channel.outlet_values())
+                        .collect();
+                    if let Err(e) = outlet.push_sample(&values) {
+                        println!("Failed to push quality data to LSL: {:?}", e);
+                    }
+                }
+                let _ = self.app_handle.emit(
+                    "channel_quality",
+                    QualityReport {
+                        session: &self.session.id,
+                        channels,
+                    },
+                );
+            }
+        }
+
+        if let Some(stage) = &mut self.emg {
+            stage
+                .processor
+                .push(&self.scaled, &mut stage.envelope, &mut stage.activations);
+            if let Err(e) = stage.envelope_outlet.push_sample(&stage.envelope) {
+                println!("Failed to push EMG envelope to LSL: {:?}", e);
+            }
+            for activation in stage.activations.drain(..) {
+                if let Err(e) = stage.marker_outlet.push_sample(&vec![activation.marker()]) {
+                    println!("Failed to push EMG marker to LSL: {:?}", e);
+                }
+            }
+        }
+
+        let mut band_powers = None;
+        if let Some(stage) = &mut self.band_power {
+            if let Some(values) = stage.band_power.push(&self.scaled) {
+                if let Err(e) = stage.outlet.push_sample(&values) {
+                    println!("Failed to push band power to LSL: {:?}", e);
+                }
+                band_powers = Some(values);
+            }
+        }
+
+        if let Some(sink) = &mut self.osc {
+            let all = || (1..=self.spec.channels).collect::<Vec<usize>>();
+            let source = sink.config().source;
+            match source {
+                OscSource::Raw if sink.due() => sink.send(&all(), OscValues::Counts(counts)),
+                OscSource::Microvolts if sink.due() => {
+                    sink.send(&all(), OscValues::Floats(&self.scaled))
+                }
+                OscSource::Filtered => {
+                    if let Some(stage) = &self.filter {
+                        if sink.due() {
+                            sink.send(&all(), OscValues::Floats(&stage.buffer));
+                        }
+                    }
+                }
+                OscSource::Envelope => {
+                    if let Some(stage) = &self.emg {
+                        if sink.due() {
+                            let channels: Vec<usize> = stage.processor.channels().collect();
+                            sink.send(&channels, OscValues::Floats(&stage.envelope));
+                        }
+                    }
+                }
+                OscSource::BandPower => {
+                    if let (Some(stage), Some(values)) = (&self.band_power, &band_powers) {
+                        sink.send(stage.band_power.channels(), OscValues::Floats(values));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(stage) = &mut self.ecg {
+            let uv = self.scaled[stage.config.channel - 1] as f64;
+            if let Some(beat) = stage.detector.push(uv) {
+                let timestamp = lsl::local_clock() - beat.delay as f64 / self.spec.sample_rate;
+                if let Err(e) =
+                    stage
+                        .marker_outlet
+                        .push_sample_ex(&vec!["R".to_string()], timestamp, true)
+                {
+                    println!("Failed to push R-peak marker to LSL: {:?}", e);
+                }
+                if let (Some(bpm), Some(rr)) = (beat.bpm(), beat.rr) {
+                    if let Err(e) =
+                        stage
+                            .rate_outlet
+                            .push_sample_ex(&vec![bpm as f32], timestamp, true)
+                    {
+                        println!("Failed to push heart rate to LSL: {:?}", e);
+                    }
+                    let _ = self.app_handle.emit(
+                        "heart_rate",
+                        HeartRate {
+                            session: &self.session.id,
+                            bpm,
+                            rr,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    fn apply_settings(&mut self) {
+        let settings = self.session.settings();
+        self.apply_filter(&settings);
+        self.apply_quality(&settings);
+        self.apply_ecg(&settings);
+        self.apply_emg(&settings);
+        self.apply_band_power(&settings);
+        self.apply_preview(&settings);
+        self.apply_spectrum(&settings);
+        self.apply_osc(&settings);
+        self.apply_mqtt(&settings);
+    }
+
+    fn apply_filter(&mut self, settings: &SessionSettings) {
+        let config = settings.filter.clone().unwrap_or_default();
+        if self
+            .filter
+            .as_ref()
+            .is_some_and(|stage| stage.config == config)
+        {
+            return;
+        }
+        let chain = FilterChain::new(&config, self.spec.sample_rate, self.spec.channels);
+        if chain.is_empty() {
+            self.filter = None;
+            return;
+        }
+
+        // A new outlet, so its `filtering` metadata describes the new chain.
+        // It keeps the source id, so LSL inlets reconnect to it on their own.
+        self.filter = None;
+        match self.filtered_outlet(&config) {
+            Ok(outlet) => {
+                self.filter = Some(FilterStage {
+                    config,
+                    chain,
+                    outlet,
+                    buffer: vec![0.0; self.spec.channels],
+                })
+            }
+            Err(e) => println!("Failed to create filtered outlet: {}", e),
+        }
+    }
+
+    fn filtered_outlet(&self, config: &FilterConfig) -> Result<StreamOutlet, String> {
+        let mut info = self.spec.stream_info(
+            "_filtered",
+            ChannelFormat::Float32,
+            self.outlets.scaling(),
+            "microvolts",
+        )?;
+
+        let mut filtering = info.desc().append_child("filtering");
+        if let Some(notch) = config.notch {
+            filtering.append_child_value("notch", &notch.to_string());
+        }
+        if let Some(highpass) = config.highpass {
+            filtering.append_child_value("highpass", &highpass.to_string());
+        }
+        if let Some((low, high)) = config.band() {
+            filtering.append_child_value("bandpass", &format!("{}-{}", low, high));
+        }
+
+        StreamOutlet::new(&info, 0, 360).map_err(|e| e.to_string())
+    }
+
+    fn apply_quality(&mut self, settings: &SessionSettings) {
+        let Some(config) = settings.quality.clone() else {
+            self.quality = None;
+            return;
+        };
+        if let Some(stage) = &self.quality {
+            if *stage.monitor.config() == config {
+                return;
+            }
+        }
+        // A zero or NaN interval would give the outlet an infinite or NaN rate.
+        if let Err(e) = config.validate() {
+            println!("Invalid quality config: {}", e);
+            self.quality = None;
+            return;
+        }
+
+        // Keep an existing outlet if it's still wanted at the same rate.
+        let outlet = match self.quality.take() {
+            Some(QualityStage {
+                monitor,
+                outlet: Some(outlet),
+            }) if config.outlet && monitor.config().interval_secs == config.interval_secs => {
+                Some(outlet)
+            }
+            _ if config.outlet => match self.quality_outlet(&config) {
+                Ok(outlet) => Some(outlet),
+                Err(e) => {
+                    println!("Failed to create quality outlet: {}", e);
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        let monitor = QualityMonitor::new(
+            config,
+            *self.outlets.scaling(),
+            self.spec.channels,
+            self.spec.sample_rate,
+        );
+        self.quality = Some(QualityStage { monitor, outlet });
+    }
+
+    fn quality_outlet(&self, config: &QualityConfig) -> Result<StreamOutlet, String> {
+        let labels: Vec<String> = (1..=self.spec.channels)
+            .flat_map(|channel| {
+                ChannelQuality::METRICS
+                    .iter()
+                    .map(move |metric| format!("CH{}_{}", channel, metric))
+            })
+            .collect();
+        let info = self.spec.derived_info(
+            "_quality",
+            "Quality",
+            1.0 / config.interval_secs,
+            ChannelFormat::Float32,
+            &labels,
+            "mixed",
+        )?;
+        StreamOutlet::new(&info, 0, 360).map_err(|e| e.to_string())
+    }
+
+    fn apply_ecg(&mut self, settings: &SessionSettings) {
+        let Some(config) = settings.ecg.clone() else {
+            self.ecg = None;
+            return;
+        };
+        if let Err(e) = config.validate(self.spec.channels) {
+            println!("Invalid ECG config: {}", e);
+            self.ecg = None;
+            return;
+        }
+
+        match &mut self.ecg {
+            Some(stage) if stage.config == config => {}
+            Some(stage) => {
+                stage.config = config;
+                stage.detector = QrsDetector::new(self.spec.sample_rate);
+            }
+            None => match self.ecg_outlets() {
+                Ok((rate_outlet, marker_outlet)) => {
+                    self.ecg = Some(EcgStage {
+                        config,
+                        detector: QrsDetector::new(self.spec.sample_rate),
+                        rate_outlet,
+                        marker_outlet,
+                    })
+                }
+                Err(e) => println!("Failed to create heart-rate outlets: {}", e),
+            },
+        }
+    }
+
+    fn ecg_outlets(&self) -> Result<(StreamOutlet, StreamOutlet), String> {
+        let rate = self.spec.derived_info(
+            "_hr",
+            "HeartRate",
+            lsl::IRREGULAR_RATE,
+            ChannelFormat::Float32,
+            &["heart_rate".to_string()],
+            "bpm",
+        )?;
+        let markers = self.spec.derived_info(
+            "_rpeaks",
+            "Markers",
+            lsl::IRREGULAR_RATE,
+            ChannelFormat::String,
+            &["r_peak".to_string()],
+            "",
+        )?;
+        Ok((
+            StreamOutlet::new(&rate, 0, 360).map_err(|e| e.to_string())?,
+            StreamOutlet::new(&markers, 0, 360).map_err(|e| e.to_string())?,
+        ))
+    }
+
+    fn apply_emg(&mut self, settings: &SessionSettings) {
+        let Some(config) = settings.emg.clone() else {
+            self.emg = None;
+            return;
+        };
+        if let Some(stage) = &self.emg {
+            if *stage.processor.config() == config {
+                return;
+            }
+        }
+
+        let processor = match EmgProcessor::new(config, self.spec.channels, self.spec.sample_rate) {
+            Ok(processor) => processor,
+            Err(e) => {
+                println!("Invalid EMG config: {}", e);
+                self.emg = None;
+                return;
+            }
+        };
+        let channels: Vec<usize> = processor.channels().collect();
+
+        // The envelope outlet's channel layout is fixed, so it's only kept if
+        // the same channels are still processed.
+        let outlets = match self.emg.take() {
+            Some(stage) if stage.processor.channels().eq(channels.iter().copied()) => {
+                Ok((stage.envelope_outlet, stage.marker_outlet))
+            }
+            _ => self.emg_outlets(&channels),
+        };
+        match outlets {
+            Ok((envelope_outlet, marker_outlet)) => {
+                self.emg = Some(EmgStage {
+                    processor,
+                    envelope_outlet,
+                    marker_outlet,
+                    envelope: Vec::with_capacity(channels.len()),
+                    activations: Vec::new(),
+                })
+            }
+            Err(e) => println!("Failed to create EMG outlets: {}", e),
+        }
+    }
+
+    fn emg_outlets(&self, channels: &[usize]) -> Result<(StreamOutlet, StreamOutlet), String> {
+        let labels: Vec<String> = channels
+            .iter()
+            .map(|channel| format!("CH{}_envelope", channel))
+            .collect();
+        let envelope = self.spec.derived_info(
+            "_emg",
+            "EMG",
+            self.spec.sample_rate,
+            ChannelFormat::Float32,
+            &labels,
+            "microvolts",
+        )?;
+        let markers = self.spec.derived_info(
+            "_emg_markers",
+            "Markers",
+            lsl::IRREGULAR_RATE,
+            ChannelFormat::String,
+            &["activation".to_string()],
+            "",
+        )?;
+        Ok((
+            StreamOutlet::new(&envelope, 0, 360).map_err(|e| e.to_string())?,
+            StreamOutlet::new(&markers, 0, 360).map_err(|e| e.to_string())?,
+        ))
+    }
+
+    fn apply_band_power(&mut self, settings: &SessionSettings) {
+        let Some(config) = settings.band_power.clone() else {
+            self.band_power = None;
+            return;
+        };
+        if let Some(stage) = &self.band_power {
+            if *stage.band_power.config() == config {
+                return;
+            }
+        }
+
+        let band_power = match BandPower::new(config, self.spec.channels, self.spec.sample_rate) {
+            Ok(band_power) => band_power,
+            Err(e) => {
+                println!("Invalid band-power config: {}", e);
+                self.band_power = None;
+                return;
+            }
+        };
+        let labels = band_power.labels();
+
+        // Keep the outlet if its layout and rate are unchanged.
+        let outlet = match self.band_power.take() {
+            Some(stage)
+                if stage.band_power.labels() == labels
+                    && stage.band_power.config().rate == band_power.config().rate =>
+            {
+                Ok(stage.outlet)
+            }
+            _ => self
+                .spec
+                .derived_info(
+                    "_bandpower",
+                    "EEG_BandPower",
+                    band_power.config().rate,
+                    ChannelFormat::Float32,
+                    &labels,
+                    "uV^2",
+                )
+                .and_then(|info| StreamOutlet::new(&info, 0, 360).map_err(|e| e.to_string())),
+        };
+        match outlet {
+            Ok(outlet) => self.band_power = Some(BandPowerStage { band_power, outlet }),
+            Err(e) => println!("Failed to create band-power outlet: {}", e),
+        }
+    }
+
+    fn apply_preview(&mut self, settings: &SessionSettings) {
+        match (&settings.preview, &self.preview) {
+            (None, _) => self.preview = None,
+            (Some(config), Some(feed)) if feed.config() == config => {}
+            (Some(config), _) => {
+                let feed = PreviewFeed::new(
+                    config.clone(),
+                    &self.session.id,
+                    self.spec.channels,
+                    self.spec.sample_rate,
+                    self.app_handle.clone(),
+                );
+                self.preview = match feed {
+                    Ok(feed) => Some(feed),
+                    Err(e) => {
+                        println!("Invalid preview config: {}", e);
+                        None
+                    }
+                };
+            }
+        }
+    }
+
+    fn apply_spectrum(&mut self, settings: &SessionSettings) {
+        match (&settings.spectrum, &self.spectrum) {
+            (None, _) => self.spectrum = None,
+            (Some(config), Some(feed)) if feed.config() == config => {}
+            (Some(config), _) => {
+                let feed = SpectrumFeed::new(
+                    config.clone(),
+                    &self.session.id,
+                    self.spec.channels,
+                    self.spec.sample_rate,
+                    self.app_handle.clone(),
+                );
+                self.spectrum = match feed {
+                    Ok(feed) => Some(feed),
+                    Err(e) => {
+                        println!("Invalid spectrum config: {}", e);
+                        None
+                    }
+                };
+            }
+        }
+    }
+
+    fn apply_osc(&mut self, settings: &SessionSettings) {
+        let Some(config) = settings.osc.clone() else {
+            self.osc = None;
+            return;
+        };
+        if self
+            .osc
+            .as_ref()
+            .is_some_and(|sink| *sink.config() == config)
+        {
+            return;
+        }
+        self.osc = Some(OscSink::new(
+            config,
+            &self.session.id,
+            self.spec.sample_rate,
+        ));
+    }
+
+    fn apply_mqtt(&mut self, settings: &SessionSettings) {
+        let Some(config) = settings.mqtt.clone() else {
+            self.mqtt = None;
+            return;
+        };
+        if self
+            .mqtt
+            .as_ref()
+            .is_some_and(|sink| *sink.config() == config)
+        {
+            return;
+        }
+        // Drop the old connection before opening one with the same client id.
+        self.mqtt = None;
+        self.mqtt = match MqttSink::new(config, &self.session.id, self.spec.sample_rate) {
+            Ok(sink) => Some(sink),
+            Err(e) => {
+                println!("Failed to create MQTT sink: {}", e);
+                None
+            }
+        };
+    }
+}
@@ -0,0 +1,244 @@
+use crate::outlet::StreamSpec;
+use crate::pipeline::Pipeline;
+use crate::recording::{self, Recording, Unit};
+use crate::scaling::{OutputConfig, OutputMode, Scaling};
+use crate::session::PLAYBACK_SESSION;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+
+// Source id of the played stream, unlike the live board's "Chords".
+const PLAYBACK_SOURCE_ID: &str = "ChordsPlayback";
+
+// Bumped by every start and stop; a playback runs while it holds the latest value.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+// Controls of the running playback, as `f64` bits for the speed.
+static SPEED: AtomicU64 = AtomicU64::new(0x3FF0_0000_0000_0000); // 1.0
+static LOOPED: AtomicBool = AtomicBool::new(false);
+
+/// How to play a recorded file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PlaybackOptions {
+    /// 1.0 plays at the recorded rate, 2.0 twice as fast.
+    pub speed: f64,
+    pub looped: bool,
+    /// Rate of CSV files without a time column.
+    pub sample_rate: Option<f64>,
+    /// ADC resolution of the board the file was recorded with, used when the
+    /// file doesn't say.
+    pub resolution: Option<u32>,
+}
+
+impl Default for PlaybackOptions {
+    fn default() -> Self {
+        PlaybackOptions {
+            speed: 1.0,
+            looped: false,
+            sample_rate: None,
+            resolution: None,
+        }
+    }
+}
+
+/// Returned by `start_playback` so the frontend can show what is playing.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaybackInfo {
+    pub channels: usize,
+    pub sample_rate: f64,
+    pub samples: usize,
+}
+
+/// The samples of a recording in the form they are published in.
+enum Samples {
+    Counts(Vec<Vec<i32>>),
+    Microvolts(Vec<Vec<f32>>),
+}
+
+impl Samples {
+    fn len(&self) -> usize {
+        match self {
+            Samples::Counts(samples) => samples.len(),
+            Samples::Microvolts(samples) => samples.len(),
+        }
+    }
+}
+
+/// Publishes a recorded file through a pipeline described by `spec`, the same
+/// description a live board gets, so downstream tools can't tell the difference.
+/// Only the source id is its own, so clients resolving a live board by source
+/// id don't bind to the playback instead.
+/// Recordings in microvolts only get the `Float32` outlet, since turning them
+/// into counts of the live board would lose everything below one count.
+pub fn start(
+    path: &str,
+    mut spec: StreamSpec,
+    scaling: Scaling,
+    options: &PlaybackOptions,
+    output: &OutputConfig,
+    app_handle: AppHandle,
+) -> Result<PlaybackInfo, String> {
+    let recording = recording::load(path, options.sample_rate)?;
+    spec.source_id = PLAYBACK_SOURCE_ID.to_string();
+    spec.channels = recording.channels;
+    spec.sample_rate = recording.sample_rate;
+    let scaling = Scaling {
+        resolution: resolution(&recording, options, scaling.resolution)?,
+        ..scaling
+    }
+    .with_config(output);
+
+    let (samples, mode) = match recording.unit {
+        Unit::Counts => (
+            Samples::Counts(
+                recording
+                    .samples
+                    .iter()
+                    .map(|sample| sample.iter().map(|&v| v as i32).collect())
+                    .collect(),
+            ),
+            output.mode.unwrap_or(OutputMode::Raw),
+        ),
+        Unit::Microvolts => (
+            Samples::Microvolts(
+                recording
+                    .samples
+                    .iter()
+                    .map(|sample| sample.iter().map(|&v| v as f32).collect())
+                    .collect(),
+            ),
+            OutputMode::Scaled,
+        ),
+    };
+    let raw_format = output
+        .raw_format
+        .unwrap_or_else(|| scaling.serial_raw_format());
+    let info = PlaybackInfo {
+        channels: spec.channels,
+        sample_rate: spec.sample_rate,
+        samples: samples.len(),
+    };
+
+    set_controls(Some(options.speed), Some(options.looped));
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    let path = path.to_string();
+    println!(
+        "Playing {} ({} channels at {} Hz)",
+        path, info.channels, info.sample_rate
+    );
+
+    thread::spawn(move || {
+        let period = 1.0 / spec.sample_rate;
+        let pipeline = Pipeline::new(
+            PLAYBACK_SESSION,
+            spec,
+            scaling,
+            mode,
+            raw_format,
+            app_handle.clone(),
+        );
+        let mut pipeline = match pipeline {
+            Ok(pipeline) => pipeline,
+            Err(e) => {
+                println!("Failed to create LSL outlet: {}", e);
+                return;
+            }
+        };
+        let stats = pipeline.stats();
+        let mut index = 0;
+        let mut next = Instant::now();
+        let mut last_print_time = Instant::now();
+
+        while GENERATION.load(Ordering::SeqCst) == generation {
+            if index == samples.len() {
+                if !LOOPED.load(Ordering::Relaxed) {
+                    break;
+                }
+                index = 0;
+            }
+            stats.add_received();
+            match &samples {
+                Samples::Counts(samples) => pipeline.push(&samples[index]),
+                Samples::Microvolts(samples) => pipeline.push_microvolts(&samples[index]),
+            }
+            index += 1;
+
+            // Reading the speed every sample lets it change smoothly mid-file.
+            let speed = f64::from_bits(SPEED.load(Ordering::Relaxed));
+            next += Duration::from_secs_f64(period / speed);
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            }
+
+            if last_print_time.elapsed() >= Duration::from_secs(1) {
+                let _ = app_handle.emit("lsl", PLAYBACK_SESSION);
+                last_print_time = Instant::now();
+            }
+        }
+
+        println!("Playback of {} finished", path);
+        let _ = app_handle.emit("playback_finished", PLAYBACK_SESSION);
+    });
+    Ok(info)
+}
+
+// The ADC resolution from the file, the options or the board, in that order.
+// Anything outside what `Scaling` can shift by is refused.
+fn resolution(recording: &Recording, options: &PlaybackOptions, board: u32) -> Result<u32, String> {
+    let resolution = recording.resolution.or(options.resolution).unwrap_or(board);
+    if !(1..=32).contains(&resolution) {
+        return Err(format!("Unsupported ADC resolution of {} bits", resolution));
+    }
+    Ok(resolution)
+}
+
+/// Changes the speed or looping of the running playback.
+pub fn set_controls(speed: Option<f64>, looped: Option<bool>) {
+    if let Some(speed) = speed.filter(|s| *s > 0.0) {
+        SPEED.store(speed.to_bits(), Ordering::Relaxed);
+    }
+    if let Some(looped) = looped {
+        LOOPED.store(looped, Ordering::Relaxed);
+    }
+}
+
+pub fn stop() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn load_csv(name: &str, csv: &str) -> Recording {
+        let path = std::env::temp_dir().join(format!("chords-{}-{}.csv", name, std::process::id()));
+        fs::write(&path, csv).unwrap();
+        let recording = recording::load(path.to_str().unwrap(), None);
+        let _ = fs::remove_file(&path);
+        recording.unwrap()
+    }
+
+    #[test]
+    fn rejects_a_zero_resolution() {
+        let recording = load_csv("zero-bits", "# resolution=0\nTime,Channel1\n0,1\n0.5,2\n");
+        let options = PlaybackOptions::default();
+        assert!(resolution(&recording, &options, 10).is_err());
+    }
+
+    #[test]
+    fn resolution_falls_back_to_the_options_then_the_board() {
+        let recording = load_csv("no-bits", "Time,Channel1\n0,1\n0.5,2\n");
+        let mut options = PlaybackOptions::default();
+        assert_eq!(resolution(&recording, &options, 10), Ok(10));
+        assert!(resolution(&recording, &options, 64).is_err());
+        options.resolution = Some(14);
+        assert_eq!(resolution(&recording, &options, 10), Ok(14));
+        options.resolution = Some(64);
+        assert!(resolution(&recording, &options, 10).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use crate::events::EventQueue;
+use crate::session::select_channels;
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+
+/// Settings of the live `preview` feed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PreviewConfig {
+    /// 1-based channels to preview; empty means all of them.
+    pub channels: Vec<usize>,
+    /// Min/max pairs per second and channel.
+    pub points_per_second: f64,
+    /// `preview` events per second.
+    pub emit_rate: f64,
+    /// Preview the output of the filter chain instead of the unfiltered signal.
+    pub filtered: bool,
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        PreviewConfig {
+            channels: Vec::new(),
+            points_per_second: 250.0,
+            emit_rate: 20.0,
+            filtered: false,
+        }
+    }
+}
+
+/// Payload of the `preview` event: one min/max envelope per channel, in microvolts.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewChunk {
+    pub session: String,
+    pub points_per_second: f64,
+    pub channels: Vec<usize>,
+    pub min: Vec<Vec<f32>>,
+    pub max: Vec<Vec<f32>>,
+    /// Chunks dropped so far because the frontend fell behind.
+    pub dropped: u64,
+}
+
+/// Min/max decimation of a session's signal for the frontend's live graph.
+pub struct PreviewFeed {
+    config: PreviewConfig,
+    session: String,
+    channels: Vec<usize>,
+    points_per_second: f64,
+    bucket: usize,
+    in_bucket: usize,
+    buckets_per_chunk: usize,
+    min: Vec<Vec<f32>>,
+    max: Vec<Vec<f32>>,
+    events: EventQueue<PreviewChunk>,
+}
+
+impl PreviewFeed {
+    /// Fails if a selected channel doesn't exist.
+    pub fn new(
+        config: PreviewConfig,
+        session: &str,
+        channels: usize,
+        sample_rate: f64,
+        app_handle: AppHandle,
+    ) -> Result<Self, String> {
+        let selected = select_channels(&config.channels, channels)?;
+        let bucket = ((sample_rate / config.points_per_second.max(1.0)) as usize).max(1);
+        let points_per_second = sample_rate / bucket as f64;
+        let buckets_per_chunk = ((points_per_second / config.emit_rate.max(1.0)) as usize).max(1);
+
+        Ok(PreviewFeed {
+            session: session.to_string(),
+            min: vec![Vec::with_capacity(buckets_per_chunk); selected.len()],
+            max: vec![Vec::with_capacity(buckets_per_chunk); selected.len()],
+            channels: selected,
+            points_per_second,
+            bucket,
+            in_bucket: 0,
+            buckets_per_chunk,
+            events: EventQueue::new("preview", app_handle),
+            config,
+        })
+    }
+
+    pub fn config(&self) -> &PreviewConfig {
+        &self.config
+    }
+
+    /// Adds one sample in microvolts.
+    pub fn push(&mut self, sample: &[f32]) {
+        let start = self.in_bucket == 0;
+        for ((min, max), &channel) in self.min.iter_mut().zip(&mut self.max).zip(&self.channels) {
+            let value = sample[channel - 1];
+            if start {
+                min.push(value);
+                max.push(value);
+            } else if let (Some(lo), Some(hi)) = (min.last_mut(), max.last_mut()) {
+                *lo = lo.min(value);
+                *hi = hi.max(value);
+            }
+        }
+
+        self.in_bucket += 1;
+        if self.in_bucket < self.bucket {
+            return;
+        }
+        self.in_bucket = 0;
+
+        if self.min.first().map_or(0, Vec::len) >= self.buckets_per_chunk {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        let width = self.buckets_per_chunk;
+        let chunk = PreviewChunk {
+            session: self.session.clone(),
+            points_per_second: self.points_per_second,
+            channels: self.channels.clone(),
+            min: self
+                .min
+                .iter_mut()
+                .map(|v| std::mem::replace(v, Vec::with_capacity(width)))
+                .collect(),
+            max: self
+                .max
+                .iter_mut()
+                .map(|v| std::mem::replace(v, Vec::with_capacity(width)))
+                .collect(),
+            dropped: self.events.dropped(),
+        };
+        self.events.send(chunk);
+    }
+}
@@ -0,0 +1,224 @@
+use crate::scaling::Scaling;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+/// Settings of the per-channel signal quality monitor.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct QualityConfig {
+    /// Length of the sliding analysis window in seconds.
+    pub window_secs: f64,
+    /// Seconds between two reports.
+    pub interval_secs: f64,
+    /// Mains frequency whose power is measured.
+    pub line_freq: f64,
+    /// A channel whose peak-to-peak range stays at or below this many counts is flat.
+    pub flat_counts: f64,
+    /// Fraction of samples at the ADC rails above which a channel is clipping.
+    pub clip_fraction: f64,
+    /// Fraction of the signal power at mains above which a channel is noisy.
+    pub noise_fraction: f64,
+    /// Also publish the metrics as a low-rate `*_quality` outlet.
+    pub outlet: bool,
+}
+
+impl Default for QualityConfig {
+    fn default() -> Self {
+        QualityConfig {
+            window_secs: 2.0,
+            interval_secs: 1.0,
+            line_freq: 50.0,
+            flat_counts: 2.0,
+            clip_fraction: 0.01,
+            noise_fraction: 0.5,
+            outlet: false,
+        }
+    }
+}
+
+impl QualityConfig {
+    /// Fails unless the window and interval are positive, finite lengths.
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, secs) in [
+            ("window", self.window_secs),
+            ("interval", self.interval_secs),
+        ] {
+            if !(secs.is_finite() && secs > 0.0) {
+                return Err(format!("Quality {} of {} s must be positive", name, secs));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelStatus {
+    Ok,
+    Flatline,
+    Clipping,
+    Noisy,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelQuality {
+    pub channel: usize,
+    pub rms_uv: f64,
+    pub peak_to_peak: f64,
+    pub clipped_fraction: f64,
+    pub line_noise_uv: f64,
+    pub line_noise_fraction: f64,
+    pub status: ChannelStatus,
+}
+
+impl ChannelQuality {
+    /// Names of the values `outlet_values` returns, per channel.
+    pub const METRICS: [&'static str; 4] = ["rms", "clipped", "line_noise", "flat"];
+
+    pub fn outlet_values(&self) -> [f32; 4] {
+        [
+            self.rms_uv as f32,
+            self.clipped_fraction as f32,
+            self.line_noise_uv as f32,
+            (self.status == ChannelStatus::Flatline) as u8 as f32,
+        ]
+    }
+}
+
+/// Sliding window statistics for every channel of a session.
+pub struct QualityMonitor {
+    config: QualityConfig,
+    scaling: Scaling,
+    sample_rate: f64,
+    window: Vec<VecDeque<i32>>,
+    capacity: usize,
+    interval: usize,
+    since_report: usize,
+}
+
+impl QualityMonitor {
+    pub fn new(config: QualityConfig, scaling: Scaling, channels: usize, sample_rate: f64) -> Self {
+        let capacity = ((config.window_secs * sample_rate) as usize).max(1);
+        let interval = ((config.interval_secs * sample_rate) as usize).max(1);
+        QualityMonitor {
+            config,
+            scaling,
+            sample_rate,
+            window: vec![VecDeque::with_capacity(capacity); channels],
+            capacity,
+            interval,
+            since_report: 0,
+        }
+    }
+
+    pub fn config(&self) -> &QualityConfig {
+        &self.config
+    }
+
+    /// Adds one sample and returns a report once every interval, as soon as
+    /// the window has filled up.
+    pub fn push(&mut self, counts: &[i32]) -> Option<Vec<ChannelQuality>> {
+        for (window, &count) in self.window.iter_mut().zip(counts) {
+            if window.len() == self.capacity {
+                window.pop_front();
+            }
+            window.push_back(count);
+        }
+
+        self.since_report += 1;
+        // Without channels there's nothing to report on.
+        let filled = self
+            .window
+            .first()
+            .is_some_and(|window| window.len() == self.capacity);
+        if self.since_report < self.interval || !filled {
+            return None;
+        }
+        self.since_report = 0;
+
+        Some(
+            (0..self.window.len())
+                .map(|channel| self.analyze(channel))
+                .collect(),
+        )
+    }
+
+    fn analyze(&self, channel: usize) -> ChannelQuality {
+        let window = &self.window[channel];
+        let n = window.len() as f64;
+        let (low, high) = self.scaling.range();
+
+        let mut min = i32::MAX;
+        let mut max = i32::MIN;
+        let mut clipped = 0usize;
+        let mut sum = 0.0;
+        for &count in window {
+            min = min.min(count);
+            max = max.max(count);
+            if count as i64 <= low || count as i64 >= high {
+                clipped += 1;
+            }
+            sum += count as f64;
+        }
+        let mean = sum / n;
+
+        // RMS around the mean, so the electrode offset doesn't count as signal.
+        let uv_per_count = self.scaling.microvolts_per_count();
+        let variance = window
+            .iter()
+            .map(|&count| (count as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        let rms_uv = variance.sqrt() * uv_per_count;
+
+        let line_rms = self.goertzel_rms(window, mean) * uv_per_count;
+        let line_noise_fraction = if rms_uv > 0.0 {
+            (line_rms / rms_uv).powi(2).min(1.0)
+        } else {
+            0.0
+        };
+
+        let peak_to_peak = (max as f64 - min as f64).max(0.0);
+        let clipped_fraction = clipped as f64 / n;
+        // A channel pinned to a rail is also flat, but clipping is the more useful diagnosis.
+        let status = if clipped_fraction > self.config.clip_fraction {
+            ChannelStatus::Clipping
+        } else if peak_to_peak <= self.config.flat_counts {
+            ChannelStatus::Flatline
+        } else if line_noise_fraction > self.config.noise_fraction {
+            ChannelStatus::Noisy
+        } else {
+            ChannelStatus::Ok
+        };
+
+        ChannelQuality {
+            channel: channel + 1,
+            rms_uv,
+            peak_to_peak,
+            clipped_fraction,
+            line_noise_uv: line_rms,
+            line_noise_fraction,
+            status,
+        }
+    }
+
+    /// RMS amplitude (in counts) of the mains component, via the Goertzel algorithm.
+    fn goertzel_rms(&self, window: &VecDeque<i32>, mean: f64) -> f64 {
+        if self.config.line_freq <= 0.0 || self.config.line_freq >= self.sample_rate / 2.0 {
+            return 0.0;
+        }
+        let n = window.len() as f64;
+        let coeff = 2.0 * (2.0 * PI * self.config.line_freq / self.sample_rate).cos();
+        let (mut s1, mut s2) = (0.0, 0.0);
+        for &count in window {
+            let s0 = count as f64 - mean + coeff * s1 - s2;
+            s2 = s1;
+            s1 = s0;
+        }
+        let power = (s1 * s1 + s2 * s2 - coeff * s1 * s2).max(0.0);
+        // Peak amplitude is 2 * sqrt(power) / n; divide by sqrt(2) for RMS.
+        2.0 * power.sqrt() / n / std::f64::consts::SQRT_2
+    }
+}
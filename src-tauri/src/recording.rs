@@ -0,0 +1,644 @@
+use std::fs;
+use std::path::Path;
+
+/// What a recording's values are measured in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unit {
+    /// Raw ADC counts, e.g. a recorded raw outlet.
+    Counts,
+    Microvolts,
+}
+
+/// A multichannel recording loaded fully into memory.
+#[derive(Clone, Debug)]
+pub struct Recording {
+    pub sample_rate: f64,
+    pub channels: usize,
+    pub unit: Unit,
+    /// ADC resolution if the file says so, as the connector's own streams do.
+    pub resolution: Option<u32>,
+    pub samples: Vec<Vec<f64>>,
+}
+
+/// Loads an XDF, CSV or EDF file, chosen by extension. `sample_rate` is only
+/// needed for CSV files without a time column.
+pub fn load(path: &str, sample_rate: Option<f64>) -> Result<Recording, String> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+    let recording = match extension.as_str() {
+        "xdf" => read_xdf(&bytes)?,
+        "csv" => read_csv(&String::from_utf8_lossy(&bytes), sample_rate)?,
+        "edf" => read_edf(&bytes)?,
+        _ => return Err(format!("Unsupported file type: {}", path)),
+    };
+    if recording.samples.is_empty() {
+        return Err(format!("{} contains no samples", path));
+    }
+    // A zero EDF record duration, say, gives an infinite or NaN rate.
+    if !(recording.sample_rate.is_finite() && recording.sample_rate > 0.0) {
+        return Err(format!("{} has no regular sample rate", path));
+    }
+    Ok(recording)
+}
+
+/// CSV as exported by Chords: an optional header row, an optional counter
+/// and time column, and one column per channel. Values are counts unless the
+/// header says µV or they have fractions. `#` lines are comments, and one of
+/// `# resolution=<bits>` gives the ADC resolution.
+fn read_csv(text: &str, sample_rate: Option<f64>) -> Result<Recording, String> {
+    fn comment(line: &str) -> Option<&str> {
+        line.trim_start().strip_prefix('#').map(str::trim)
+    }
+    let resolution = text
+        .lines()
+        .filter_map(comment)
+        .filter_map(|c| c.strip_prefix("resolution="))
+        .find_map(|v| v.trim().parse().ok());
+    let mut lines = text
+        .lines()
+        .filter(|l| !l.trim().is_empty() && comment(l).is_none())
+        .peekable();
+    let first: Vec<String> = match lines.peek() {
+        Some(line) => line.split(',').map(|f| f.trim().to_lowercase()).collect(),
+        None => return Err("CSV file is empty".to_string()),
+    };
+
+    let has_header = first.iter().any(|f| f.parse::<f64>().is_err());
+    let (time, skip, microvolts) = if has_header {
+        lines.next();
+        let position = |pattern: &str| first.iter().position(|f| f.contains(pattern));
+        let time = position("time");
+        let skip: Vec<usize> = first
+            .iter()
+            .enumerate()
+            .filter(|(i, f)| {
+                Some(*i) == time
+                    || f.contains("counter")
+                    || f.contains("index")
+                    || f.as_str() == "sample"
+            })
+            .map(|(i, _)| i)
+            .collect();
+        let microvolts = first.iter().any(|f| f.contains("uv") || f.contains("µv"));
+        (time, skip, microvolts)
+    } else {
+        (None, Vec::new(), false)
+    };
+
+    let mut samples = Vec::new();
+    let mut times = Vec::new();
+    for (number, line) in lines.enumerate() {
+        let mut sample = Vec::new();
+        for (i, field) in line.split(',').enumerate() {
+            let value: f64 = field
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid value '{}' in data row {}", field, number + 1))?;
+            if Some(i) == time {
+                times.push(value);
+            }
+            if !skip.contains(&i) {
+                sample.push(value);
+            }
+        }
+        samples.push(sample);
+    }
+
+    let channels = samples.first().map_or(0, Vec::len);
+    if samples.iter().any(|s| s.len() != channels) {
+        return Err("CSV rows have different numbers of columns".to_string());
+    }
+    let sample_rate = match (sample_rate, times.first(), times.last()) {
+        (Some(rate), _, _) => rate,
+        (None, Some(&first), Some(&last)) if last > first => {
+            (times.len() - 1) as f64 / (last - first)
+        }
+        _ => return Err("CSV file has no time column; pass the sample rate".to_string()),
+    };
+    let fractional = samples.iter().flatten().any(|v| v.fract() != 0.0);
+
+    Ok(Recording {
+        sample_rate,
+        channels,
+        unit: if microvolts || fractional {
+            Unit::Microvolts
+        } else {
+            Unit::Counts
+        },
+        resolution,
+        samples,
+    })
+}
+
+/// EDF with all ordinary signals at the same rate; annotation signals are skipped.
+fn read_edf(bytes: &[u8]) -> Result<Recording, String> {
+    let field = |start: usize, len: usize| -> Result<String, String> {
+        bytes
+            .get(start..start + len)
+            .map(|f| String::from_utf8_lossy(f).trim().to_string())
+            .ok_or_else(|| "EDF header is truncated".to_string())
+    };
+    let number = |start: usize, len: usize| -> Result<f64, String> {
+        let text = field(start, len)?;
+        text.parse()
+            .map_err(|_| format!("Invalid number '{}' in EDF header", text))
+    };
+
+    let header_len = number(184, 8)? as usize;
+    let records = number(236, 8)? as i64;
+    let duration = number(244, 8)?;
+    let signals = number(252, 4)? as usize;
+    // Each signal has 256 bytes of header, so a count the file can't hold is
+    // garbage.
+    if signals
+        .checked_mul(256)
+        .and_then(|len| len.checked_add(256))
+        .filter(|&len| len <= bytes.len())
+        .is_none()
+    {
+        return Err("EDF header is truncated".to_string());
+    }
+
+    // Per-signal fields are stored field by field, each for all signals.
+    let column = |offset: usize, len: usize, signal: usize| 256 + offset * signals + len * signal;
+    let mut layout = Vec::with_capacity(signals);
+    for s in 0..signals {
+        let label = field(column(0, 16, s), 16)?;
+        let dimension = field(column(96, 8, s), 8)?;
+        let physical = (number(column(104, 8, s), 8)?, number(column(112, 8, s), 8)?);
+        let digital = (number(column(120, 8, s), 8)?, number(column(128, 8, s), 8)?);
+        let per_record = number(column(216, 8, s), 8)? as usize;
+
+        let unit = match dimension.to_lowercase().as_str() {
+            "nv" => 1e-3,
+            "mv" => 1e3,
+            "v" => 1e6,
+            _ => 1.0,
+        };
+        let span = digital.1 - digital.0;
+        let gain = if span != 0.0 {
+            (physical.1 - physical.0) / span * unit
+        } else {
+            unit
+        };
+        let offset = physical.0 * unit - gain * digital.0;
+        layout.push((label, per_record, gain, offset));
+    }
+
+    let used: Vec<usize> = (0..signals)
+        .filter(|&s| layout[s].0 != "EDF Annotations")
+        .collect();
+    let per_record = used.first().map_or(0, |&s| layout[s].1);
+    if used.iter().any(|&s| layout[s].1 != per_record) {
+        return Err("EDF signals with different sample rates aren't supported".to_string());
+    }
+
+    let record_len = layout
+        .iter()
+        .try_fold(0usize, |len, l| l.1.checked_mul(2)?.checked_add(len))
+        .ok_or_else(|| "EDF data records are too long".to_string())?;
+    let data = bytes.get(header_len..).unwrap_or(&[]);
+    let available = data.len().checked_div(record_len).unwrap_or(0);
+    let records = if records < 0 {
+        available
+    } else {
+        (records as usize).min(available)
+    };
+
+    let mut samples = vec![vec![0.0; used.len()]; records * per_record];
+    for r in 0..records {
+        let mut position = r * record_len;
+        for (s, (_, count, gain, offset)) in layout.iter().enumerate() {
+            if let Some(c) = used.iter().position(|&u| u == s) {
+                for i in 0..*count {
+                    let at = position + i * 2;
+                    let digital = i16::from_le_bytes([data[at], data[at + 1]]) as f64;
+                    samples[r * per_record + i][c] = digital * gain + offset;
+                }
+            }
+            position += count * 2;
+        }
+    }
+
+    Ok(Recording {
+        sample_rate: per_record as f64 / duration,
+        channels: used.len(),
+        unit: Unit::Microvolts,
+        resolution: None,
+        samples,
+    })
+}
+
+struct XdfStream {
+    id: u32,
+    channels: usize,
+    sample_rate: f64,
+    format: String,
+    resolution: Option<u32>,
+    samples: Vec<Vec<f64>>,
+}
+
+/// XDF as written by LabRecorder. Plays the first regularly sampled numeric
+/// stream, preferring an integer one since that is the connector's raw outlet.
+fn read_xdf(bytes: &[u8]) -> Result<Recording, String> {
+    if !bytes.starts_with(b"XDF:") {
+        return Err("Not an XDF file".to_string());
+    }
+    let mut reader = Reader { bytes, position: 4 };
+    let mut streams: Vec<XdfStream> = Vec::new();
+
+    while reader.position < bytes.len() {
+        let len = reader.varlen()?;
+        let end = reader.position.checked_add(len).ok_or_else(truncated)?;
+        let tag = reader.u16()?;
+        match tag {
+            // StreamHeader
+            2 => {
+                let id = reader.u32()?;
+                let rest = end.checked_sub(reader.position).ok_or_else(truncated)?;
+                let xml = String::from_utf8_lossy(reader.take(rest)?);
+                let tag = |name: &str| xml_value(&xml, name);
+                streams.push(XdfStream {
+                    id,
+                    channels: tag("channel_count")
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(0),
+                    sample_rate: tag("nominal_srate")
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(0.0),
+                    format: tag("channel_format").unwrap_or("").to_string(),
+                    resolution: tag("resolution").and_then(|v| v.parse().ok()),
+                    samples: Vec::new(),
+                });
+            }
+            // Samples
+            3 => {
+                let id = reader.u32()?;
+                if let Some(stream) = streams.iter_mut().find(|s| s.id == id) {
+                    let width = match stream.format.as_str() {
+                        "int8" => 1,
+                        "int16" => 2,
+                        "int32" | "float32" => 4,
+                        "int64" | "double64" => 8,
+                        _ => 0,
+                    };
+                    if width > 0 {
+                        let count = reader.varlen()?;
+                        for _ in 0..count {
+                            if reader.u8()? == 8 {
+                                reader.take(8)?;
+                            }
+                            let len = stream.channels.checked_mul(width).ok_or_else(truncated)?;
+                            let values = reader.take(len)?;
+                            stream.samples.push(
+                                values
+                                    .chunks_exact(width)
+                                    .map(|v| decode_xdf_value(&stream.format, v))
+                                    .collect(),
+                            );
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        if end < reader.position {
+            return Err("XDF chunk overruns its length".to_string());
+        }
+        reader.position = end;
+    }
+
+    let playable = |s: &&XdfStream| s.sample_rate > 0.0 && s.channels > 0 && !s.samples.is_empty();
+    let stream = streams
+        .iter()
+        .filter(playable)
+        .find(|s| s.format == "int16" || s.format == "int32")
+        .or_else(|| streams.iter().find(playable))
+        .ok_or_else(|| "XDF file has no regularly sampled numeric stream".to_string())?;
+
+    Ok(Recording {
+        sample_rate: stream.sample_rate,
+        channels: stream.channels,
+        unit: if stream.format.starts_with("int") {
+            Unit::Counts
+        } else {
+            Unit::Microvolts
+        },
+        resolution: stream.resolution,
+        samples: stream.samples.clone(),
+    })
+}
+
+fn decode_xdf_value(format: &str, v: &[u8]) -> f64 {
+    match format {
+        "int8" => v[0] as i8 as f64,
+        "int16" => i16::from_le_bytes([v[0], v[1]]) as f64,
+        "int32" => i32::from_le_bytes(v.try_into().unwrap()) as f64,
+        "int64" => i64::from_le_bytes(v.try_into().unwrap()) as f64,
+        "float32" => f32::from_le_bytes(v.try_into().unwrap()) as f64,
+        _ => f64::from_le_bytes(v.try_into().unwrap()),
+    }
+}
+
+/// Text of the first `<name>` element. Enough for the flat stream headers
+/// XDF writers produce.
+fn xml_value<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&format!("</{}>", name))? + start;
+    Some(xml[start..end].trim())
+}
+
+fn truncated() -> String {
+    "XDF file is truncated".to_string()
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let slice = self
+            .position
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(self.position..end))
+            .ok_or_else(truncated)?;
+        self.position += len;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// XDF's variable-length integer: a byte giving the width (1, 4 or 8),
+    /// then the value.
+    fn varlen(&mut self) -> Result<usize, String> {
+        match self.u8()? {
+            1 => Ok(self.u8()? as usize),
+            4 => Ok(self.u32()? as usize),
+            8 => Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()) as usize),
+            n => Err(format!("Invalid XDF length width {}", n)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn csv_with_counter_and_time_columns() {
+        let csv = "Counter,Time,Channel1,Channel2\n\
+                   0,0.000,512,500\n\
+                   1,0.002,513,501\n\
+                   2,0.004,514,502\n";
+        let recording = read_csv(csv, None).unwrap();
+        assert_close(recording.sample_rate, 500.0);
+        assert_eq!(recording.channels, 2);
+        assert_eq!(recording.unit, Unit::Counts);
+        assert_eq!(recording.samples[2], vec![514.0, 502.0]);
+    }
+
+    #[test]
+    fn csv_in_microvolts() {
+        let csv = "time,ch1 (uV)\n0,10\n0.5,11\n";
+        assert_eq!(read_csv(csv, None).unwrap().unit, Unit::Microvolts);
+        let fractional = "1.5,2\n3,4\n";
+        let recording = read_csv(fractional, Some(250.0)).unwrap();
+        assert_eq!(recording.unit, Unit::Microvolts);
+        assert_eq!(recording.channels, 2);
+    }
+
+    #[test]
+    fn csv_comments_give_the_resolution() {
+        let csv = "# Chords recording\n# resolution=12\nTime,Channel1\n0,1\n0.5,2\n";
+        let recording = read_csv(csv, None).unwrap();
+        assert_eq!(recording.resolution, Some(12));
+        assert_eq!(recording.samples.len(), 2);
+    }
+
+    #[test]
+    fn csv_without_time_needs_a_rate() {
+        let csv = "1,2\n3,4\n";
+        assert!(read_csv(csv, None).is_err());
+        assert_close(read_csv(csv, Some(250.0)).unwrap().sample_rate, 250.0);
+    }
+
+    #[test]
+    fn csv_rejects_ragged_and_invalid_rows() {
+        assert!(read_csv("1,2\n3\n", Some(250.0)).is_err());
+        assert!(read_csv("1,2\n3,x\n", Some(250.0)).is_err());
+        assert!(read_csv("\n\n", Some(250.0)).is_err());
+    }
+
+    // An EDF file with one EEG signal of `per_record` samples and an
+    // annotation signal of two, at ±3276.8 µV over the full 16-bit range.
+    fn edf(duration: &str, per_record: usize, records: &[Vec<i16>]) -> Vec<u8> {
+        let field = |out: &mut Vec<u8>, text: &str, width: usize| {
+            out.extend_from_slice(format!("{:<width$}", text, width = width).as_bytes());
+        };
+        let mut bytes = Vec::new();
+        field(&mut bytes, "0", 8);
+        field(&mut bytes, "", 80);
+        field(&mut bytes, "", 80);
+        field(&mut bytes, "01.01.24", 8);
+        field(&mut bytes, "00.00.00", 8);
+        field(&mut bytes, "768", 8);
+        field(&mut bytes, "", 44);
+        field(&mut bytes, &records.len().to_string(), 8);
+        field(&mut bytes, duration, 8);
+        field(&mut bytes, "2", 4);
+        // Each field for both signals before the next field.
+        let samples = per_record.to_string();
+        let per_signal = [
+            (16, ["EEG Fp1", "EDF Annotations"]),
+            (80, ["", ""]),
+            (8, ["uV", ""]),
+            (8, ["-3276.8", "-1"]),
+            (8, ["3276.7", "1"]),
+            (8, ["-32768", "-32768"]),
+            (8, ["32767", "32767"]),
+            (80, ["", ""]),
+            (8, [samples.as_str(), "2"]),
+            (32, ["", ""]),
+        ];
+        for (width, values) in per_signal {
+            for value in values {
+                field(&mut bytes, value, width);
+            }
+        }
+        for record in records {
+            for value in record {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[0; 4]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn edf_scales_to_microvolts_and_skips_annotations() {
+        let bytes = edf("1", 4, &[vec![0, 10, -10, 100], vec![1, 2, 3, 4]]);
+        let recording = read_edf(&bytes).unwrap();
+        assert_close(recording.sample_rate, 4.0);
+        assert_eq!(recording.channels, 1);
+        assert_eq!(recording.unit, Unit::Microvolts);
+        assert_eq!(recording.samples.len(), 8);
+        assert_close(recording.samples[1][0], 1.0);
+        assert_close(recording.samples[2][0], -1.0);
+        assert_close(recording.samples[7][0], 0.4);
+    }
+
+    #[test]
+    fn edf_ignores_a_partial_last_record() {
+        let mut bytes = edf("1", 4, &[vec![0; 4], vec![0; 4]]);
+        bytes.truncate(bytes.len() - 3);
+        assert_eq!(read_edf(&bytes).unwrap().samples.len(), 4);
+    }
+
+    #[test]
+    fn edf_rejects_truncated_and_impossible_headers() {
+        let bytes = edf("1", 4, &[vec![0; 4]]);
+        assert!(read_edf(&bytes[..200]).is_err());
+        let mut huge = bytes.clone();
+        huge[252..256].copy_from_slice(b"9999");
+        assert!(read_edf(&huge).is_err());
+    }
+
+    #[test]
+    fn edf_zero_duration_is_rejected_on_load() {
+        let path = std::env::temp_dir().join(format!("chords-zero-{}.edf", std::process::id()));
+        fs::write(&path, edf("0", 4, &[vec![0; 4]])).unwrap();
+        let result = load(path.to_str().unwrap(), None);
+        let _ = fs::remove_file(&path);
+        assert!(result.unwrap_err().contains("sample rate"));
+    }
+
+    fn xdf_chunk(tag: u16, content: &[u8]) -> Vec<u8> {
+        let mut chunk = vec![4];
+        chunk.extend_from_slice(&(content.len() as u32 + 2).to_le_bytes());
+        chunk.extend_from_slice(&tag.to_le_bytes());
+        chunk.extend_from_slice(content);
+        chunk
+    }
+
+    fn xdf_header(id: u32, format: &str, channels: usize) -> Vec<u8> {
+        let xml = format!(
+            "<?xml version=\"1.0\"?><info><channel_count>{}</channel_count>\
+             <nominal_srate>250</nominal_srate><channel_format>{}</channel_format>\
+             <desc><resinfo><resolution>10</resolution></resinfo></desc></info>",
+            channels, format
+        );
+        xdf_chunk(2, &[&id.to_le_bytes()[..], xml.as_bytes()].concat())
+    }
+
+    // Samples with a timestamp on the first only, as LabRecorder writes them.
+    fn xdf_samples(id: u32, samples: &[Vec<u8>]) -> Vec<u8> {
+        let mut content = id.to_le_bytes().to_vec();
+        content.extend_from_slice(&[1, samples.len() as u8]);
+        for (i, values) in samples.iter().enumerate() {
+            if i == 0 {
+                content.push(8);
+                content.extend_from_slice(&1.5f64.to_le_bytes());
+            } else {
+                content.push(0);
+            }
+            content.extend_from_slice(values);
+        }
+        xdf_chunk(3, &content)
+    }
+
+    fn int16s(values: &[i16]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn float32s(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn xdf(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = b"XDF:".to_vec();
+        bytes.extend(xdf_chunk(1, b"<?xml version=\"1.0\"?><info/>"));
+        for chunk in chunks {
+            bytes.extend_from_slice(chunk);
+        }
+        bytes
+    }
+
+    #[test]
+    fn xdf_prefers_the_integer_stream() {
+        let bytes = xdf(&[
+            xdf_header(1, "float32", 2),
+            xdf_header(2, "int16", 2),
+            xdf_samples(1, &[float32s(&[0.5, 1.5])]),
+            xdf_samples(2, &[int16s(&[1, -2]), int16s(&[3, 4])]),
+        ]);
+        let recording = read_xdf(&bytes).unwrap();
+        assert_eq!(recording.unit, Unit::Counts);
+        assert_eq!(recording.resolution, Some(10));
+        assert_close(recording.sample_rate, 250.0);
+        assert_eq!(recording.samples, vec![vec![1.0, -2.0], vec![3.0, 4.0]]);
+    }
+
+    #[test]
+    fn xdf_float_stream_is_in_microvolts() {
+        let bytes = xdf(&[
+            xdf_header(1, "float32", 2),
+            xdf_samples(1, &[float32s(&[0.5, 1.5])]),
+        ]);
+        let recording = read_xdf(&bytes).unwrap();
+        assert_eq!(recording.unit, Unit::Microvolts);
+        assert_eq!(recording.samples, vec![vec![0.5, 1.5]]);
+    }
+
+    #[test]
+    fn xdf_rejects_truncated_files() {
+        let bytes = xdf(&[
+            xdf_header(1, "int16", 2),
+            xdf_samples(1, &[int16s(&[1, 2]), int16s(&[3, 4])]),
+        ]);
+        let error = read_xdf(&bytes[..bytes.len() - 3]).unwrap_err();
+        assert_eq!(error, truncated());
+        assert!(read_xdf(b"not an xdf file").is_err());
+    }
+
+    #[test]
+    fn xdf_rejects_lengths_past_the_end() {
+        // A chunk length near usize::MAX must not overflow.
+        let mut bytes = xdf(&[]);
+        bytes.push(8);
+        bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        assert!(read_xdf(&bytes).is_err());
+
+        // As must a header too short for its own tag and stream id.
+        let mut bytes = xdf(&[]);
+        bytes.extend_from_slice(&[1, 2, 2, 0, 1, 0, 0, 0]);
+        assert!(read_xdf(&bytes).is_err());
+    }
+}
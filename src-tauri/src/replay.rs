@@ -0,0 +1,108 @@
+use crate::capture::CaptureReader;
+use crate::decoder::Protocol;
+use crate::pipeline::Pipeline;
+use crate::scaling::{OutputConfig, OutputMode, RawFormat};
+use crate::session::REPLAY_SESSION;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+
+// Bumped by every start and stop; a replay runs while it holds the latest value.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+// Longest a paced replay sleeps before checking whether it was stopped.
+const STOP_CHECK: Duration = Duration::from_millis(100);
+
+/// Feeds a raw capture through its transport's decoder into a pipeline of its
+/// own, reproducing the recorded session's outlets. In real time the records
+/// are paced by their capture timestamps, otherwise they go as fast as possible.
+pub fn start(
+    path: &str,
+    realtime: bool,
+    output: &OutputConfig,
+    app_handle: AppHandle,
+) -> Result<(), String> {
+    let mut reader = CaptureReader::open(path)?;
+    let header = reader.header.clone();
+    // Its own stream, so LSL clients can't mistake it for the live one.
+    let mut spec = header.spec.clone();
+    spec.name = format!("{}_Replay", spec.name);
+    spec.source_id = REPLAY_SESSION.to_string();
+    let scaling = header.scaling.with_config(output);
+    let mode = output.mode.unwrap_or(OutputMode::Raw);
+    // The default of the transport that was captured, as when it was live.
+    let raw_format = output.raw_format.unwrap_or(match header.protocol {
+        Protocol::Serial { .. } => scaling.serial_raw_format(),
+        Protocol::Wifi { .. } | Protocol::Ble { .. } => RawFormat::Float32,
+    });
+    let path = path.to_string();
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+
+    println!(
+        "Replaying {} capture of {} from {}",
+        header.session, header.started, path
+    );
+
+    thread::spawn(move || {
+        let pipeline = Pipeline::new(
+            REPLAY_SESSION,
+            spec,
+            scaling,
+            mode,
+            raw_format,
+            app_handle.clone(),
+        );
+        let mut pipeline = match pipeline {
+            Ok(pipeline) => pipeline,
+            Err(e) => {
+                println!("Failed to create LSL outlet: {}", e);
+                return;
+            }
+        };
+        let stats = pipeline.stats();
+        let mut decoder = header.protocol.decoder();
+        let mut bytes = Vec::new();
+        let start = Instant::now();
+        let mut last_print_time = Instant::now();
+
+        'records: while GENERATION.load(Ordering::SeqCst) == generation {
+            let micros = match reader.next_record(&mut bytes) {
+                Ok(Some(micros)) => micros,
+                Ok(None) => break,
+                Err(e) => {
+                    println!("Failed to read {}: {}", path, e);
+                    break;
+                }
+            };
+            if realtime {
+                // In slices, so a stop doesn't wait out a long gap between records.
+                let due = Duration::from_micros(micros);
+                loop {
+                    if GENERATION.load(Ordering::SeqCst) != generation {
+                        break 'records;
+                    }
+                    let elapsed = start.elapsed();
+                    if due <= elapsed {
+                        break;
+                    }
+                    thread::sleep((due - elapsed).min(STOP_CHECK));
+                }
+            }
+
+            decoder.feed(&bytes, &stats, |sample| pipeline.push(sample));
+
+            if last_print_time.elapsed() >= Duration::from_secs(1) {
+                let _ = app_handle.emit("lsl", REPLAY_SESSION);
+                last_print_time = Instant::now();
+            }
+        }
+
+        println!("Replay of {} finished", path);
+        let _ = app_handle.emit("replay_finished", REPLAY_SESSION);
+    });
+    Ok(())
+}
+
+pub fn stop() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
@@ -0,0 +1,222 @@
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread::{self, Thread};
+use std::time::Duration;
+
+/// Bounded single-producer single-consumer queue of fixed-width samples,
+/// handing samples from a transport reader to the pipeline without locks.
+///
+/// Overflow policy: when the consumer falls behind and the ring is full, the
+/// incoming sample is dropped and the producer told so; samples already
+/// queued are kept, so what reaches the outlets stays in order with a gap.
+struct Ring {
+    width: usize,
+    /// Capacity in samples, a power of two.
+    capacity: usize,
+    slots: Box<[AtomicI32]>,
+    /// Samples written so far; only the producer stores it.
+    head: AtomicUsize,
+    /// Samples read so far; only the consumer stores it.
+    tail: AtomicUsize,
+    closed: AtomicBool,
+    /// The thread consuming, once it has waited.
+    consumer: OnceLock<Thread>,
+    /// Set while the consumer is parked or about to be, so the producer
+    /// knows to wake it.
+    waiting: AtomicBool,
+}
+
+impl Ring {
+    fn wake(&self) {
+        if self.waiting.swap(false, Ordering::SeqCst) {
+            if let Some(consumer) = self.consumer.get() {
+                consumer.unpark();
+            }
+        }
+    }
+}
+
+/// Creates a ring holding at least `capacity` samples of `width` values.
+pub fn ring(width: usize, capacity: usize) -> (Producer, Consumer) {
+    let capacity = capacity.max(2).next_power_of_two();
+    let ring = Arc::new(Ring {
+        width,
+        capacity,
+        slots: (0..width * capacity).map(|_| AtomicI32::new(0)).collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        closed: AtomicBool::new(false),
+        consumer: OnceLock::new(),
+        waiting: AtomicBool::new(false),
+    });
+    (Producer { ring: ring.clone() }, Consumer { ring })
+}
+
+pub struct Producer {
+    ring: Arc<Ring>,
+}
+
+impl Producer {
+    /// Queues one sample, or drops it if the ring is full. Returns false when
+    /// it was dropped. Values beyond the ring's width are ignored, missing
+    /// ones read as zero.
+    pub fn push(&mut self, sample: &[i32]) -> bool {
+        let ring = &self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        if head.wrapping_sub(ring.tail.load(Ordering::Acquire)) == ring.capacity {
+            return false;
+        }
+
+        let start = (head & (ring.capacity - 1)) * ring.width;
+        for (i, slot) in ring.slots[start..start + ring.width].iter().enumerate() {
+            slot.store(sample.get(i).copied().unwrap_or(0), Ordering::Relaxed);
+        }
+        ring.head.store(head.wrapping_add(1), Ordering::SeqCst);
+        ring.wake();
+        true
+    }
+}
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        self.ring.closed.store(true, Ordering::SeqCst);
+        self.ring.wake();
+    }
+}
+
+/// What `Consumer::pop` found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pop {
+    Sample,
+    Empty,
+    /// Empty and the producer is gone, so nothing more will come.
+    Closed,
+}
+
+pub struct Consumer {
+    ring: Arc<Ring>,
+}
+
+impl Consumer {
+    /// Takes the oldest sample into `sample`.
+    pub fn pop(&mut self, sample: &mut Vec<i32>) -> Pop {
+        let ring = &self.ring;
+        // Read before the head, so a producer that queued its last sample and
+        // then closed is never taken for closed while that sample is unread.
+        let closed = ring.closed.load(Ordering::Acquire);
+        let tail = ring.tail.load(Ordering::Relaxed);
+        if tail == ring.head.load(Ordering::Acquire) {
+            return if closed { Pop::Closed } else { Pop::Empty };
+        }
+
+        let start = (tail & (ring.capacity - 1)) * ring.width;
+        sample.clear();
+        sample.extend(
+            ring.slots[start..start + ring.width]
+                .iter()
+                .map(|slot| slot.load(Ordering::Relaxed)),
+        );
+        ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Pop::Sample
+    }
+
+    /// Parks the thread until the producer queues a sample or goes away, or
+    /// `timeout` passes. Always called from the same thread.
+    pub fn wait(&mut self, timeout: Duration) {
+        let ring = &self.ring;
+        ring.consumer.get_or_init(thread::current);
+        ring.waiting.store(true, Ordering::SeqCst);
+        // Checked after announcing the wait, so a sample the producer queued
+        // in between isn't slept through.
+        let empty = ring.tail.load(Ordering::Relaxed) == ring.head.load(Ordering::SeqCst);
+        if empty && !ring.closed.load(Ordering::SeqCst) {
+            thread::park_timeout(timeout);
+        }
+        ring.waiting.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn pops_in_order() {
+        let (mut producer, mut consumer) = ring(2, 4);
+        let mut sample = Vec::new();
+        assert_eq!(consumer.pop(&mut sample), Pop::Empty);
+        // Enough rounds to wrap the ring a few times.
+        for i in 0..10 {
+            assert!(producer.push(&[i, -i]));
+            assert!(producer.push(&[i + 100, 0]));
+            assert_eq!(consumer.pop(&mut sample), Pop::Sample);
+            assert_eq!(sample, [i, -i]);
+            assert_eq!(consumer.pop(&mut sample), Pop::Sample);
+            assert_eq!(sample, [i + 100, 0]);
+        }
+        assert_eq!(consumer.pop(&mut sample), Pop::Empty);
+    }
+
+    #[test]
+    fn drops_new_samples_when_full() {
+        let (mut producer, mut consumer) = ring(1, 3);
+        for i in 0..4 {
+            assert!(producer.push(&[i]));
+        }
+        assert!(!producer.push(&[4]));
+        let mut sample = Vec::new();
+        for i in 0..4 {
+            consumer.pop(&mut sample);
+            assert_eq!(sample, [i]);
+        }
+        assert!(producer.push(&[5]));
+        consumer.pop(&mut sample);
+        assert_eq!(sample, [5]);
+    }
+
+    #[test]
+    fn pads_and_truncates_to_the_width() {
+        let (mut producer, mut consumer) = ring(3, 2);
+        producer.push(&[1]);
+        producer.push(&[1, 2, 3, 4]);
+        let mut sample = Vec::new();
+        consumer.pop(&mut sample);
+        assert_eq!(sample, [1, 0, 0]);
+        consumer.pop(&mut sample);
+        assert_eq!(sample, [1, 2, 3]);
+    }
+
+    #[test]
+    fn closes_once_drained() {
+        let (mut producer, mut consumer) = ring(1, 2);
+        producer.push(&[7]);
+        drop(producer);
+        let mut sample = Vec::new();
+        assert_eq!(consumer.pop(&mut sample), Pop::Sample);
+        assert_eq!(sample, [7]);
+        assert_eq!(consumer.pop(&mut sample), Pop::Closed);
+    }
+
+    #[test]
+    fn wait_wakes_on_push() {
+        let (mut producer, mut consumer) = ring(1, 2);
+        let pusher = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            producer.push(&[1]);
+            producer
+        });
+        let started = Instant::now();
+        let mut sample = Vec::new();
+        while consumer.pop(&mut sample) != Pop::Sample {
+            consumer.wait(Duration::from_secs(10));
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(sample, [1]);
+
+        drop(pusher.join().unwrap());
+        consumer.wait(Duration::from_secs(10));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(consumer.pop(&mut sample), Pop::Closed);
+    }
+}
@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+
+/// Which LSL outlets a session publishes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    /// Raw ADC counts in the session's `RawFormat`. The default.
+    Raw,
+    /// Calibrated microvolts as `Float32`.
+    Scaled,
+    /// Raw and scaled as two separate outlets.
+    Both,
+}
+
+impl OutputMode {
+    pub fn has_raw(self) -> bool {
+        matches!(self, OutputMode::Raw | OutputMode::Both)
+    }
+
+    pub fn has_scaled(self) -> bool {
+        matches!(self, OutputMode::Scaled | OutputMode::Both)
+    }
+}
+
+/// Channel format of the raw outlet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RawFormat {
+    /// What serial boards of up to 16 bits have always published. Counts of
+    /// 16-bit unipolar ADCs above 32767 wrap around to negative values.
+    Int16,
+    /// Needed for ADCs wider than 16 bits, and holds the full range of 16-bit
+    /// unipolar ones such as the GIGA R1 without wrapping.
+    Int32,
+    /// Counts as floats, what the WiFi and BLE streams have always published.
+    Float32,
+}
+
+/// Output settings passed from the frontend when a stream is started.
+/// Anything left out falls back to the detected board's defaults.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputConfig {
+    pub mode: Option<OutputMode>,
+    /// Left out, each transport keeps the format it has always published.
+    pub raw_format: Option<RawFormat>,
+    /// ADC reference voltage in volts.
+    pub vref: Option<f64>,
+    /// Analog front-end gain, e.g. the BioAmp EXG Pill gain.
+    pub gain: Option<f64>,
+}
+
+/// Conversion from ADC counts to microvolts at the electrodes.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Scaling {
+    pub resolution: u32,
+    pub vref: f64,
+    pub gain: f64,
+    /// Counts are two's complement around zero volts instead of offset binary.
+    #[serde(default)]
+    pub bipolar: bool,
+}
+
+impl Scaling {
+    pub fn new(resolution: u32, vref: f64) -> Self {
+        Scaling {
+            resolution,
+            vref,
+            gain: 1.0,
+            bipolar: false,
+        }
+    }
+
+    /// Applies the user overrides from `config` on top of the board defaults.
+    pub fn with_config(mut self, config: &OutputConfig) -> Self {
+        if let Some(vref) = config.vref.filter(|v| *v > 0.0) {
+            self.vref = vref;
+        }
+        if let Some(gain) = config.gain.filter(|g| *g > 0.0) {
+            self.gain = gain;
+        }
+        self
+    }
+
+    /// Most boards sample a unipolar signal biased at half the reference,
+    /// so mid-scale is zero volts. Bipolar frontends need no offset.
+    pub fn offset(&self) -> f64 {
+        if self.bipolar {
+            0.0
+        } else {
+            (1u64 << (self.resolution - 1)) as f64
+        }
+    }
+
+    /// A bipolar ADC spans -vref to +vref, a unipolar one 0 to vref.
+    pub fn microvolts_per_count(&self) -> f64 {
+        let span = if self.bipolar {
+            1u64 << (self.resolution - 1)
+        } else {
+            1u64 << self.resolution
+        };
+        self.vref / span as f64 / self.gain * 1e6
+    }
+
+    /// Lowest and highest count the ADC can produce.
+    pub fn range(&self) -> (i64, i64) {
+        let full = 1i64 << self.resolution;
+        if self.bipolar {
+            (-full / 2, full / 2 - 1)
+        } else {
+            (0, full - 1)
+        }
+    }
+
+    /// Whether counts don't fit in an `i16`, as those of 16-bit unipolar
+    /// ADCs, which go up to 65535, don't.
+    pub fn is_wide(&self) -> bool {
+        let (min, max) = self.range();
+        min < i16::MIN as i64 || max > i16::MAX as i64
+    }
+
+    /// The raw format serial boards have always had: `Int16` unless the ADC
+    /// is wider than 16 bits.
+    pub fn serial_raw_format(&self) -> RawFormat {
+        if self.resolution > 16 {
+            RawFormat::Int32
+        } else {
+            RawFormat::Int16
+        }
+    }
+
+    pub fn to_microvolts(self, count: i32) -> f32 {
+        ((count as f64 - self.offset()) * self.microvolts_per_count()) as f32
+    }
+
+    /// Inverse of `to_microvolts`, clamped to the ADC's range.
+    pub fn to_counts(self, microvolts: f64) -> i32 {
+        let (min, max) = self.range();
+        (microvolts / self.microvolts_per_count() + self.offset())
+            .round()
+            .clamp(min as f64, max as f64) as i32
+    }
+}
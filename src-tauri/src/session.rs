@@ -0,0 +1,132 @@
+use crate::ecg::EcgConfig;
+use crate::eeg::BandPowerConfig;
+use crate::emg::EmgConfig;
+use crate::filter::FilterConfig;
+use crate::mqtt::MqttConfig;
+use crate::osc::OscConfig;
+use crate::preview::PreviewConfig;
+use crate::quality::QualityConfig;
+use crate::spectrum_feed::SpectrumConfig;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+// Session ids, matching the source ids reported through the `lsl` event.
+pub const SERIAL_SESSION: &str = "uidserial007";
+pub const WIFI_SESSION: &str = "uidwifi007";
+pub const BLE_SESSION: &str = "uidbluetooth007";
+pub const REPLAY_SESSION: &str = "uidreplay007";
+pub const PLAYBACK_SESSION: &str = "uidplayback007";
+
+/// Processing settings that can be changed while a session is streaming.
+/// Everything is off until the frontend turns it on.
+#[derive(Clone, Debug, Default)]
+pub struct SessionSettings {
+    pub filter: Option<FilterConfig>,
+    pub quality: Option<QualityConfig>,
+    pub ecg: Option<EcgConfig>,
+    pub emg: Option<EmgConfig>,
+    pub band_power: Option<BandPowerConfig>,
+    pub preview: Option<PreviewConfig>,
+    pub spectrum: Option<SpectrumConfig>,
+    /// File the transport's raw bytes are captured to.
+    pub capture: Option<String>,
+    pub osc: Option<OscConfig>,
+    pub mqtt: Option<MqttConfig>,
+}
+
+/// Channel count and sampling rate of a session's stream.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StreamShape {
+    pub channels: usize,
+    pub sample_rate: f64,
+}
+
+/// Settings of one session. They outlive the connection, so the frontend can
+/// configure a session before it starts and keep the settings across reconnects.
+pub struct Session {
+    pub id: String,
+    settings: Mutex<SessionSettings>,
+    revision: AtomicU64,
+    shape: Mutex<Option<StreamShape>>,
+}
+
+impl Session {
+    pub fn settings(&self) -> SessionSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    /// Bumped on every update so streaming loops can cheaply poll for changes.
+    pub fn revision(&self) -> u64 {
+        self.revision.load(Ordering::Acquire)
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut SessionSettings)) {
+        f(&mut self.settings.lock().unwrap());
+        self.revision.fetch_add(1, Ordering::Release);
+    }
+
+    /// The stream the session last published, which settings commands check
+    /// new settings against; `None` before its first stream.
+    pub fn shape(&self) -> Option<StreamShape> {
+        *self.shape.lock().unwrap()
+    }
+
+    pub fn set_shape(&self, shape: StreamShape) {
+        *self.shape.lock().unwrap() = Some(shape);
+    }
+}
+
+/// The 1-based channels a config selects from a stream of `channels`, all of
+/// them if it names none. Fails on a channel the stream doesn't have.
+pub fn select_channels(selected: &[usize], channels: usize) -> Result<Vec<usize>, String> {
+    if channels == 0 {
+        return Err("The stream has no channels".to_string());
+    }
+    if selected.is_empty() {
+        return Ok((1..=channels).collect());
+    }
+    match selected.iter().find(|c| !(1..=channels).contains(*c)) {
+        Some(c) => Err(format!(
+            "Channel {} doesn't exist; the stream has {}",
+            c, channels
+        )),
+        None => Ok(selected.to_vec()),
+    }
+}
+
+lazy_static! {
+    static ref SESSIONS: Mutex<HashMap<String, Arc<Session>>> = Mutex::new(HashMap::new());
+}
+
+pub fn get_or_create(id: &str) -> Arc<Session> {
+    SESSIONS
+        .lock()
+        .unwrap()
+        .entry(id.to_string())
+        .or_insert_with(|| {
+            Arc::new(Session {
+                id: id.to_string(),
+                settings: Mutex::new(SessionSettings::default()),
+                // Starts at 1 so a fresh pipeline always applies the settings once.
+                revision: AtomicU64::new(1),
+                shape: Mutex::new(None),
+            })
+        })
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_channels() {
+        assert_eq!(select_channels(&[], 3), Ok(vec![1, 2, 3]));
+        assert_eq!(select_channels(&[3, 1], 3), Ok(vec![3, 1]));
+        assert!(select_channels(&[0], 3).is_err());
+        assert!(select_channels(&[2, 4], 3).is_err());
+        assert!(select_channels(&[], 0).is_err());
+    }
+}
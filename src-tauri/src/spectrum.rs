@@ -0,0 +1,98 @@
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// Welch power spectral density estimate: Hann-windowed, mean-removed segments
+/// averaged over a longer stretch of signal.
+pub struct Welch {
+    fft: Arc<dyn Fft<f64>>,
+    window: Vec<f64>,
+    step: usize,
+    sample_rate: f64,
+    // Converts |X|^2 into a one-sided density in units^2/Hz.
+    scale: f64,
+    buffer: Vec<Complex<f64>>,
+}
+
+impl Welch {
+    /// `overlap` is the fraction (0 to just below 1) that consecutive segments share.
+    pub fn new(segment: usize, overlap: f64, sample_rate: f64) -> Self {
+        let segment = segment.max(2);
+        let window: Vec<f64> = (0..segment)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f64 / segment as f64).cos())
+            .collect();
+        let power: f64 = window.iter().map(|w| w * w).sum();
+        let step = ((segment as f64 * (1.0 - overlap.clamp(0.0, 0.95))) as usize).max(1);
+
+        Welch {
+            fft: FftPlanner::new().plan_fft_forward(segment),
+            scale: 1.0 / (sample_rate * power),
+            window,
+            step,
+            sample_rate,
+            buffer: vec![Complex::default(); segment],
+        }
+    }
+
+    pub fn segment_len(&self) -> usize {
+        self.window.len()
+    }
+
+    /// Frequency spacing of the bins in Hz.
+    pub fn resolution(&self) -> f64 {
+        self.sample_rate / self.window.len() as f64
+    }
+
+    /// Number of bins `psd` produces, from DC up to Nyquist.
+    pub fn bins(&self) -> usize {
+        self.window.len() / 2 + 1
+    }
+
+    /// Writes the averaged PSD of `data` into `psd`. `data` should be at least
+    /// one segment long; shorter input yields all zeros.
+    pub fn psd(&mut self, data: &[f64], psd: &mut Vec<f64>) {
+        let segment = self.window.len();
+        psd.clear();
+        psd.resize(self.bins(), 0.0);
+
+        let mut segments = 0;
+        let mut start = 0;
+        while start + segment <= data.len() {
+            let chunk = &data[start..start + segment];
+            let mean = chunk.iter().sum::<f64>() / segment as f64;
+            for ((out, &x), &w) in self.buffer.iter_mut().zip(chunk).zip(&self.window) {
+                *out = Complex::new((x - mean) * w, 0.0);
+            }
+            self.fft.process(&mut self.buffer);
+            for (bin, value) in psd.iter_mut().enumerate() {
+                let mut p = self.buffer[bin].norm_sqr() * self.scale;
+                // Fold the negative frequencies in, except for DC and Nyquist.
+                if bin != 0 && !(segment % 2 == 0 && bin == segment / 2) {
+                    p *= 2.0;
+                }
+                *value += p;
+            }
+            segments += 1;
+            start += self.step;
+        }
+
+        if segments > 1 {
+            for value in psd.iter_mut() {
+                *value /= segments as f64;
+            }
+        }
+    }
+}
+
+/// Integrates `psd` over `[low, high)` Hz.
+pub fn band_power(psd: &[f64], resolution: f64, low: f64, high: f64) -> f64 {
+    psd.iter()
+        .enumerate()
+        .filter(|(bin, _)| {
+            let freq = *bin as f64 * resolution;
+            freq >= low && freq < high
+        })
+        .map(|(_, p)| p * resolution)
+        .sum()
+}
@@ -0,0 +1,134 @@
+use crate::events::EventQueue;
+use crate::session::select_channels;
+use crate::spectrum::Welch;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use tauri::AppHandle;
+
+/// Settings of the rolling `spectrum` feed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SpectrumConfig {
+    /// 1-based channels to analyze; empty means all of them.
+    pub channels: Vec<usize>,
+    /// FFT window length in seconds.
+    pub window_secs: f64,
+    /// Fraction that consecutive windows overlap.
+    pub overlap: f64,
+    /// Number of windows averaged into each spectrum.
+    pub averages: usize,
+    /// `spectrum` events per second.
+    pub rate: f64,
+    /// Highest frequency sent to the frontend; Nyquist if unset.
+    pub max_freq: Option<f64>,
+}
+
+impl Default for SpectrumConfig {
+    fn default() -> Self {
+        SpectrumConfig {
+            channels: Vec::new(),
+            window_secs: 1.0,
+            overlap: 0.5,
+            averages: 4,
+            rate: 4.0,
+            max_freq: None,
+        }
+    }
+}
+
+/// Payload of the `spectrum` event: power spectral density in µV²/Hz, bin
+/// `i` being at `i * resolution` Hz.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Spectrum {
+    pub session: String,
+    pub resolution: f64,
+    pub channels: Vec<usize>,
+    pub power: Vec<Vec<f32>>,
+}
+
+/// Rolling per-channel power spectrum for the frontend.
+pub struct SpectrumFeed {
+    config: SpectrumConfig,
+    session: String,
+    channels: Vec<usize>,
+    welch: Welch,
+    history: Vec<VecDeque<f64>>,
+    capacity: usize,
+    bins: usize,
+    interval: usize,
+    since_update: usize,
+    psd: Vec<f64>,
+    events: EventQueue<Spectrum>,
+}
+
+impl SpectrumFeed {
+    /// Fails if a selected channel doesn't exist.
+    pub fn new(
+        config: SpectrumConfig,
+        session: &str,
+        channels: usize,
+        sample_rate: f64,
+        app_handle: AppHandle,
+    ) -> Result<Self, String> {
+        let selected = select_channels(&config.channels, channels)?;
+        let segment = ((config.window_secs * sample_rate) as usize).max(2);
+        let welch = Welch::new(segment, config.overlap, sample_rate);
+        let step = ((segment as f64 * (1.0 - config.overlap.clamp(0.0, 0.95))) as usize).max(1);
+        let capacity = segment + step * config.averages.max(1).saturating_sub(1);
+        let bins = match config.max_freq {
+            Some(max) => ((max / welch.resolution()) as usize + 1).min(welch.bins()),
+            None => welch.bins(),
+        };
+
+        Ok(SpectrumFeed {
+            session: session.to_string(),
+            history: vec![VecDeque::with_capacity(capacity); selected.len()],
+            channels: selected,
+            welch,
+            capacity,
+            bins,
+            interval: ((sample_rate / config.rate.max(0.1)) as usize).max(1),
+            since_update: 0,
+            psd: Vec::new(),
+            events: EventQueue::new("spectrum", app_handle),
+            config,
+        })
+    }
+
+    pub fn config(&self) -> &SpectrumConfig {
+        &self.config
+    }
+
+    /// Adds one sample in microvolts.
+    pub fn push(&mut self, sample: &[f32]) {
+        for (history, &channel) in self.history.iter_mut().zip(&self.channels) {
+            if history.len() == self.capacity {
+                history.pop_front();
+            }
+            history.push_back(sample[channel - 1] as f64);
+        }
+
+        self.since_update += 1;
+        let filled = self
+            .history
+            .first()
+            .is_some_and(|h| h.len() >= self.welch.segment_len());
+        if self.since_update < self.interval || !filled {
+            return;
+        }
+        self.since_update = 0;
+
+        let mut power = Vec::with_capacity(self.history.len());
+        for history in &mut self.history {
+            self.welch.psd(history.make_contiguous(), &mut self.psd);
+            power.push(self.psd[..self.bins].iter().map(|&p| p as f32).collect());
+        }
+        self.events.send(Spectrum {
+            session: self.session.clone(),
+            resolution: self.welch.resolution(),
+            channels: self.channels.clone(),
+            power,
+        });
+    }
+}
@@ -0,0 +1,235 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+
+/// Counters of one session, updated lock-free by the transport reader and the
+/// pipeline and reported once per second as the `stream_stats` event.
+#[derive(Default)]
+pub struct StreamStats {
+    /// Samples decoded by the transport.
+    received: AtomicU64,
+    /// Samples that went through the pipeline.
+    delivered: AtomicU64,
+    bytes: AtomicU64,
+    lost: AtomicU64,
+    duplicate: AtomicU64,
+    corrupt: AtomicU64,
+    /// Samples dropped because the pipeline fell behind the reader.
+    overflow: AtomicU64,
+    /// Arrival jitter of the last full second in ms, as `f64` bits.
+    jitter: AtomicU64,
+}
+
+impl StreamStats {
+    pub fn add_received(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_delivered(&self) {
+        self.delivered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes(&self, bytes: usize) {
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_lost(&self, samples: u64) {
+        self.lost.fetch_add(samples, Ordering::Relaxed);
+    }
+
+    pub fn add_duplicate(&self) {
+        self.duplicate.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_corrupt(&self) {
+        self.corrupt.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_overflow(&self) {
+        self.overflow.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the outcome of a sequence counter check.
+    pub fn add_sequence(&self, sequence: Sequence) {
+        match sequence {
+            Sequence::Next => {}
+            Sequence::Lost(samples) => self.add_lost(samples),
+            Sequence::Duplicate => self.add_duplicate(),
+        }
+    }
+
+    fn set_jitter(&self, ms: f64) {
+        self.jitter.store(ms.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Payload of the `stream_stats` event.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsReport {
+    pub session: String,
+    /// Measured samples per second.
+    pub rate: f64,
+    pub nominal_rate: f64,
+    /// Measured rate as a percentage of the nominal rate.
+    pub received_percent: f64,
+    /// Totals since the session started.
+    pub lost: u64,
+    pub duplicate: u64,
+    pub corrupt: u64,
+    /// Samples dropped because the pipeline couldn't keep up.
+    pub overflow: u64,
+    pub bytes_per_second: f64,
+    /// Standard deviation of sample arrival times from a steady clock, in ms.
+    pub jitter_ms: f64,
+    /// Samples decoded but not yet through the pipeline.
+    pub queue_depth: u64,
+}
+
+/// Turns the counters into reports, each covering the time since the last one.
+pub struct StatsSampler {
+    session: String,
+    nominal_rate: f64,
+    last: Instant,
+    last_delivered: u64,
+    last_bytes: u64,
+}
+
+impl StatsSampler {
+    pub fn new(session: &str, nominal_rate: f64) -> Self {
+        StatsSampler {
+            session: session.to_string(),
+            nominal_rate,
+            last: Instant::now(),
+            last_delivered: 0,
+            last_bytes: 0,
+        }
+    }
+
+    /// Time since the last report.
+    pub fn elapsed(&self) -> Duration {
+        self.last.elapsed()
+    }
+
+    pub fn report(&mut self, stats: &StreamStats) -> StatsReport {
+        let elapsed = self.last.elapsed().as_secs_f64();
+        self.last = Instant::now();
+        let received = stats.received.load(Ordering::Relaxed);
+        let delivered = stats.delivered.load(Ordering::Relaxed);
+        let bytes = stats.bytes.load(Ordering::Relaxed);
+        let overflow = stats.overflow.load(Ordering::Relaxed);
+
+        let rate = delivered.saturating_sub(self.last_delivered) as f64 / elapsed;
+        let report = StatsReport {
+            session: self.session.clone(),
+            rate,
+            nominal_rate: self.nominal_rate,
+            received_percent: if self.nominal_rate > 0.0 {
+                rate / self.nominal_rate * 100.0
+            } else {
+                0.0
+            },
+            lost: stats.lost.load(Ordering::Relaxed),
+            duplicate: stats.duplicate.load(Ordering::Relaxed),
+            corrupt: stats.corrupt.load(Ordering::Relaxed),
+            overflow,
+            bytes_per_second: bytes.saturating_sub(self.last_bytes) as f64 / elapsed,
+            jitter_ms: f64::from_bits(stats.jitter.load(Ordering::Relaxed)),
+            queue_depth: received.saturating_sub(delivered + overflow),
+        };
+        self.last_delivered = delivered;
+        self.last_bytes = bytes;
+        report
+    }
+}
+
+/// Emits `stream_stats` for `stats` every second until every other owner
+/// of `stats` is gone.
+pub fn spawn_reporter(
+    stats: Arc<StreamStats>,
+    session: String,
+    nominal_rate: f64,
+    app_handle: AppHandle,
+) {
+    thread::spawn(move || {
+        let mut sampler = StatsSampler::new(&session, nominal_rate);
+        while Arc::strong_count(&stats) > 1 {
+            thread::sleep(Duration::from_secs(1));
+            let _ = app_handle.emit("stream_stats", sampler.report(&stats));
+        }
+    });
+}
+
+/// Measures how far sample arrivals stray from an evenly spaced clock. The
+/// result is published once per second, each second being measured on its own
+/// so clock drift between board and host doesn't accumulate.
+pub struct JitterMeter {
+    period: f64,
+    start: Instant,
+    count: u64,
+    sum: f64,
+    sum_squares: f64,
+}
+
+impl JitterMeter {
+    pub fn new(sample_rate: f64) -> Self {
+        JitterMeter {
+            period: 1.0 / sample_rate,
+            start: Instant::now(),
+            count: 0,
+            sum: 0.0,
+            sum_squares: 0.0,
+        }
+    }
+
+    pub fn record(&mut self, stats: &StreamStats) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        if elapsed >= 1.0 && self.count > 1 {
+            let n = self.count as f64;
+            let variance = (self.sum_squares / n - (self.sum / n).powi(2)).max(0.0);
+            stats.set_jitter(variance.sqrt() * 1000.0);
+            self.start = Instant::now();
+            self.count = 0;
+            self.sum = 0.0;
+            self.sum_squares = 0.0;
+            return;
+        }
+
+        let offset = elapsed - self.count as f64 * self.period;
+        self.count += 1;
+        self.sum += offset;
+        self.sum_squares += offset * offset;
+    }
+}
+
+/// Result of checking a packet's 8-bit sample counter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sequence {
+    Next,
+    /// This many samples were skipped.
+    Lost(u64),
+    Duplicate,
+}
+
+/// Follows the wrapping 8-bit sample counter the boards put in every packet.
+#[derive(Default)]
+pub struct SequenceTracker {
+    last: Option<u8>,
+}
+
+impl SequenceTracker {
+    pub fn check(&mut self, counter: u8) -> Sequence {
+        let sequence = match self.last {
+            Some(last) if counter == last => Sequence::Duplicate,
+            Some(last) if counter != last.wrapping_add(1) => {
+                Sequence::Lost(counter.wrapping_sub(last).wrapping_sub(1) as u64)
+            }
+            _ => Sequence::Next,
+        };
+        self.last = Some(counter);
+        sequence
+    }
+}
@@ -8,6 +8,28 @@ import { SmoothieChart, TimeSeries } from 'smoothie';
 import { FontAwesomeIcon } from '@fortawesome/react-fontawesome';
 import { faWindows, faApple, faDebian } from '@fortawesome/free-brands-svg-icons';
 
+// Session ids of the transports, as the backend names them.
+const SESSIONS = {
+  serial: 'uidserial007',
+  wifi: 'uidwifi007',
+  bluetooth: 'uidbluetooth007',
+} as const;
+
+const PREVIEW_COLORS = ['#22c55e', '#3b82f6', '#ec4899', '#f59e0b', '#a855f7', '#14b8a6', '#ef4444', '#eab308'];
+
+// Turns on the backend's decimated `preview` feed for a session. Settings
+// outlive connections, so this can be done before the stream starts.
+const enablePreview = async (session: string) => {
+  try {
+    await core.invoke('set_preview_config', {
+      sessionId: session,
+      config: { pointsPerSecond: 100, emitRate: 10 },
+    });
+  } catch (error) {
+    console.error('Failed to enable the signal preview:', error);
+  }
+};
+
 const App = () => {
   const [deviceConnected, setDeviceConnected] = useState(false);
   const portRef = useRef<unknown>(null);
@@ -17,11 +39,19 @@ const App = () => {
   const [samplelost, setSamplelost] = useState<number | undefined>(0);
   const [connecting, setconnecting] = useState(false);
   const [totalSample, setTotalSample] = useState(0);
+  const [handshake, setHandshake] = useState<string | null>(null);
+  const [detecting, setDetecting] = useState(false);
+  const [autoStart, setAutoStart] = useState(false);
+  // Boards found by a detection that turned up more than one, to pick from.
+  const [foundBoards, setFoundBoards] = useState<{ port: string; baudRate: number; board: { name: string } }[]>([]);
   const isProcessing = useRef(false);
   const [scane, setScane] = useState(false);
   const chartRef = useRef<HTMLCanvasElement>(null);
   const smoothieChartRef = useRef<SmoothieChart | null>(null);
   const timeSeriesRef = useRef<TimeSeries | null>(null);
+  const previewRef = useRef<HTMLCanvasElement>(null);
+  const previewChartRef = useRef<SmoothieChart | null>(null);
+  const previewSeriesRef = useRef<Map<number, TimeSeries>>(new Map());
 
 
   const ConnectserialDevice = async () => {
@@ -29,21 +59,109 @@ const App = () => {
       setconnecting(true);
       setTotalSample(0);
       isProcessing.current = true;
-      const portName = await core.invoke('detect_arduino') as string;
-      portRef.current = portName;
-      await core.invoke('start_streaming', { portName: portRef.current, stream_name: "UDL" });
-      setDeviceConnected(true);
+      setDetecting(true);
+      let port: string;
+      try {
+        const last = await core.invoke('find_last_serial_device') as { port: string };
+        port = last.port;
+      } catch {
+        const boards = await core.invoke('detect_arduino') as { port: string; baudRate: number; board: { name: string } }[];
+        if (boards.length > 1) {
+          // Let the user choose rather than guess.
+          setDetecting(false);
+          setconnecting(false);
+          setHandshake(null);
+          setFoundBoards(boards);
+          return;
+        }
+        port = boards[0].port;
+      }
+      setDetecting(false);
+      await StreamSerialPort(port);
+    } catch (error) {
+      console.error('Failed to connect to device:', error);
+      setDetecting(false);
       setconnecting(false);
+      setHandshake(null);
+    }
+  };
+
+  const StreamSerialPort = async (port: string) => {
+    setconnecting(true);
+    portRef.current = port;
+    await enablePreview(SESSIONS.serial);
+    await core.invoke('start_streaming', { portName: portRef.current, stream_name: "UDL" });
+    setDeviceConnected(true);
+    setconnecting(false);
+  };
+
+  const ChooseBoard = async (port: string) => {
+    setFoundBoards([]);
+    try {
+      await StreamSerialPort(port);
     } catch (error) {
       console.error('Failed to connect to device:', error);
+      setconnecting(false);
+      setHandshake(null);
+      setActiveButton(null);
     }
   };
 
-  const ConnectwifiDevice = async () => {
+  const CancelDetection = async () => {
+    await core.invoke('cancel_detection');
+  };
+
+  const ToggleAutoStart = async (enabled: boolean) => {
+    try {
+      await core.invoke('set_auto_start', { enabled });
+      setAutoStart(enabled);
+    } catch (error) {
+      console.error('Failed to save auto-start setting:', error);
+    }
+  };
+
+  // Reconnect to the last device on launch when auto-start is on.
+  useEffect(() => {
+    const startLastDevice = async () => {
+      const { device, autoStart } = await core.invoke('get_last_device') as {
+        device: { transport: 'serial' | 'ble' | 'wifi'; id?: string; url?: string } | null;
+        autoStart: boolean;
+      };
+      setAutoStart(autoStart);
+      if (!autoStart || !device) return;
+
+      setActiveButton(device.transport === 'ble' ? 'bluetooth' : device.transport);
+      switch (device.transport) {
+        case 'serial':
+          ConnectserialDevice();
+          break;
+        case 'wifi':
+          ConnectwifiDevice(device.url);
+          break;
+        case 'ble':
+          try {
+            setTotalSample(0);
+            isProcessing.current = true;
+            await enablePreview(SESSIONS.bluetooth);
+            await core.invoke<string>('connect_to_ble', { deviceId: device.id });
+            setDeviceConnected(true);
+          } catch (error) {
+            console.error('Failed to reconnect to BLE device:', error);
+            setActiveButton(null);
+          }
+          break;
+      }
+    };
+
+    startLastDevice();
+  }, []);
+
+  const ConnectwifiDevice = async (url?: string) => {
     try {
       setconnecting(true);
       setTotalSample(0);
-      await core.invoke("start_wifistreaming");
+      await enablePreview(SESSIONS.wifi);
+      await core.invoke("start_wifistreaming", { url });
       isProcessing.current = true;
    
     } catch (error) {
@@ -105,10 +223,35 @@ const App = () => {
     // Stream to canvas with faster updates for smoother rendering
     smoothieChartRef.current.streamTo(chartRef.current, 50);
   };
+
+  const createPreviewChart = () => {
+    if (!previewRef.current) return;
+
+    // One series per channel is added as the first preview for it arrives.
+    previewChartRef.current = new SmoothieChart({
+      limitFPS: 30,
+      grid: {
+        strokeStyle: 'rgb(75, 85, 99)',
+        lineWidth: 1,
+        millisPerLine: 1000,
+        verticalSections: 4,
+        borderVisible: false
+      },
+      labels: {
+        fillStyle: '#ffffff',
+        fontSize: 12,
+        precision: 0,
+      },
+      responsive: true,
+      millisPerPixel: 10,
+    });
+    previewChartRef.current.streamTo(previewRef.current, 200);
+  };
   
   useEffect(() => {
     if (deviceConnected) {
       createChart();
+      createPreviewChart();
 
       // Cleanup function
       return () => {
@@ -117,6 +260,11 @@ const App = () => {
           smoothieChartRef.current = null;
           timeSeriesRef.current = null;
         }
+        if (previewChartRef.current) {
+          previewChartRef.current.stop();
+          previewChartRef.current = null;
+          previewSeriesRef.current.clear();
+        }
       };
     }
   }, [deviceConnected]); // This effect runs when deviceConnected changes
@@ -134,9 +282,11 @@ const App = () => {
       });
       unlistenFns.push(unlistenBleDevices);
 
-      const unlistenSamplerate = await listen('samplerate', (event) => {
-        const value = Math.ceil(Number(event.payload));
+      const unlistenStats = await listen('stream_stats', (event) => {
+        const stats = event.payload as { rate: number; lost: number };
+        const value = Math.ceil(stats.rate);
         setSamplerate(value);
+        setSamplelost(stats.lost);
         setTotalSample(prev => prev + value);
 
         if (timeSeriesRef.current) {
@@ -146,7 +296,41 @@ const App = () => {
         }
       });
 
-      unlistenFns.push(unlistenSamplerate);
+      unlistenFns.push(unlistenStats);
+
+      // Min/max pairs per channel, newest last; each pair is drawn as a
+      // vertical stroke so spikes survive the decimation.
+      const unlistenPreview = await listen('preview', (event) => {
+        const { pointsPerSecond, channels, min, max } = event.payload as {
+          pointsPerSecond: number;
+          channels: number[];
+          min: number[][];
+          max: number[][];
+        };
+        const chart = previewChartRef.current;
+        if (!chart) return;
+        const step = 1000 / pointsPerSecond;
+        const now = Date.now();
+        channels.forEach((channel, i) => {
+          let series = previewSeriesRef.current.get(channel);
+          if (!series) {
+            series = new TimeSeries();
+            previewSeriesRef.current.set(channel, series);
+            chart.addTimeSeries(series, {
+              strokeStyle: PREVIEW_COLORS[(channel - 1) % PREVIEW_COLORS.length],
+              lineWidth: 1,
+            });
+          }
+          const points = min[i].length;
+          for (let j = 0; j < points; j++) {
+            const time = now - (points - 1 - j) * step;
+            series.append(time, min[i][j]);
+            series.append(time + step / 2, max[i][j]);
+          }
+        });
+      });
+
+      unlistenFns.push(unlistenPreview);
 
       const unlistenconnection = await listen('connection', () => {
         setDeviceConnected(true);
@@ -154,11 +338,36 @@ const App = () => {
       });
 
       unlistenFns.push(unlistenconnection);
-      
-      const unlistenSamplelost = await listen('samplelost', (event) => {
-        setSamplelost(event.payload as number);
+
+      const handshakeLabels: Record<string, string> = {
+        'reset-wait': 'Waiting for the board to reset',
+        identify: 'Identifying board',
+        configure: 'Configuring',
+        start: 'Starting stream',
+        streaming: 'Streaming',
+        failed: 'Handshake failed, retrying',
+      };
+      const unlistenHandshake = await listen('handshake', (event) => {
+        const { port, state, attempt } = event.payload as { port: string; state: string; attempt: number };
+        const label = handshakeLabels[state] ?? state;
+        setHandshake(`${port}: ${label}${attempt > 1 ? ` (attempt ${attempt})` : ''}`);
       });
-      unlistenFns.push(unlistenSamplelost);
+
+      unlistenFns.push(unlistenHandshake);
+
+      const detectionLabels: Record<string, string> = {
+        probing: 'Probing',
+        found: 'Found',
+        'not-found': 'No board',
+        cancelled: 'Cancelled',
+      };
+      const unlistenDetection = await listen('detection', (event) => {
+        const { port, status, board } = event.payload as { port: string; status: string; board: string | null };
+        const label = detectionLabels[status] ?? status;
+        setHandshake(`${port}: ${label}${board ? ` ${board}` : ''}`);
+      });
+
+      unlistenFns.push(unlistenDetection);
     };
 
     setupListeners();
@@ -198,6 +407,14 @@ const App = () => {
                 Chords
               </div>
               LSL Connector </h2>
+            <label className="flex items-center gap-2 text-white text-sm ml-auto mr-4 cursor-pointer">
+              <input
+                type="checkbox"
+                checked={autoStart}
+                onChange={(e) => ToggleAutoStart(e.target.checked)}
+              />
+              Auto-start last device
+            </label>
             <button
               onClick={() => handleClick("https://github.com/upsidedownlabs/Chords-Arduino-Firmware")}
               className="flex items-center cursor-pointer gap-2 px-3 py-1 bg-gray-600 hover:bg-gray-700 dark:bg-gray-500 dark:hover:bg-gray-600 text-white rounded-md text-sm transition-colors"
@@ -323,6 +540,12 @@ const App = () => {
                   </div>
                 </div>
               </div>
+              <div className="bg-gray-800 rounded-lg p-2 border border-gray-700 mt-2">
+                <h3 className="text-lg font-medium text-white mb-2">Signal Preview (µV)</h3>
+                <div className="h-[12.5rem] bg-black rounded-lg border border-gray-600">
+                  <canvas ref={previewRef} className="w-full h-full rounded-lg" />
+                </div>
+              </div>
             </>
           ) : (
             <>
@@ -401,6 +624,7 @@ const App = () => {
                         key={device.id}
                         className="border border-gray-300 dark:border-gray-600 rounded-lg p-3 cursor-pointer hover:bg-gray-50 dark:hover:bg-gray-700 flex justify-between items-center transition-colors"
                         onClick={async () => {
+                          await enablePreview(SESSIONS.bluetooth);
                           await core.invoke<string>("connect_to_ble", { deviceId: device.id });
                           setDeviceConnected(true);
                           setScane(false);
@@ -429,6 +653,45 @@ const App = () => {
             </div>
           </div>
         )}
+        {foundBoards.length > 0 && (
+          <div className="fixed inset-0 bg-black bg-opacity-50 flex items-center justify-center z-50">
+            <div className="bg-white dark:bg-gray-800 rounded-lg shadow-xl max-w-md w-full mx-4 border border-gray-200 dark:border-gray-700 max-h-[90vh] flex flex-col">
+              <div className="border-b border-gray-200 dark:border-gray-700 p-4 flex justify-between items-center flex-shrink-0">
+                <div>
+                  <h2 className="text-xl font-semibold text-gray-900 dark:text-white">Choose a Board</h2>
+                  <p className="text-sm text-gray-500 dark:text-gray-400">{foundBoards.length} boards were found</p>
+                </div>
+                <button
+                  onClick={() => {
+                    setFoundBoards([]);
+                    setActiveButton(null);
+                  }}
+                  className="text-gray-400 hover:text-gray-600 dark:hover:text-gray-300 transition-colors"
+                >
+                  <X className="h-5 w-5" />
+                </button>
+              </div>
+
+              <div className="p-6 text-gray-900 dark:text-white overflow-y-auto flex-1 min-h-0 space-y-2">
+                {foundBoards.map((found) => (
+                  <div
+                    key={found.port}
+                    className="border border-gray-300 dark:border-gray-600 rounded-lg p-3 cursor-pointer hover:bg-gray-50 dark:hover:bg-gray-700 flex justify-between items-center transition-colors"
+                    onClick={() => ChooseBoard(found.port)}
+                  >
+                    <div className="flex items-center">
+                      <Usb className="h-5 w-5 mr-3 text-blue-600 dark:text-blue-400" />
+                      <span className="truncate">{found.board.name}</span>
+                    </div>
+                    <span className="text-sm text-gray-500 dark:text-gray-400 ml-2">
+                      {found.port} at {found.baudRate} baud
+                    </span>
+                  </div>
+                ))}
+              </div>
+            </div>
+          </div>
+        )}
         {connecting && (
           <div className="fixed inset-0 bg-black bg-opacity-50 flex items-center justify-center z-50">
             <div className="bg-white dark:bg-gray-800 rounded-lg shadow-xl max-w-md w-full mx-4 border border-gray-200 dark:border-gray-700 max-h-[90vh] flex flex-col">
@@ -437,6 +700,17 @@ const App = () => {
                 <div className="text-center">
                   <div className="animate-spin rounded-full h-8 w-8 border-b-2 border-blue-600 dark:border-blue-400 mx-auto mb-4"></div>
                   <p className="text-gray-600 dark:text-gray-400">Connecting...</p>
+                  {handshake && (
+                    <p className="text-sm text-gray-500 dark:text-gray-500 mt-1">{handshake}</p>
+                  )}
+                  {detecting && (
+                    <button
+                      onClick={CancelDetection}
+                      className="mt-4 px-4 py-2 text-sm rounded-md border border-gray-300 dark:border-gray-600 text-gray-700 dark:text-gray-300 hover:bg-gray-100 dark:hover:bg-gray-700"
+                    >
+                      Cancel
+                    </button>
+                  )}
                 </div>
               </div>
             </div>